@@ -0,0 +1,196 @@
+use git2::Time;
+
+/// Parses a date given on the command line into a `Time` that keeps the date's own timezone.
+///
+/// Accepts RFC 3339 dates such as `2023-01-15T10:30:00+02:00` (a space may be used instead of the
+/// `T`, and before the offset, as in `git log --date=iso`), as well as git's internal
+/// `<unix timestamp> <+hhmm>` format.
+pub fn parse_date(date: &str) -> Result<Time, String> {
+    let date = date.trim();
+    parse_git_internal(date)
+        .or_else(|| parse_rfc3339(date))
+        .ok_or_else(|| {
+            format!(
+                "'{}' is not a valid date. Expected an RFC 3339 date such as \
+                 '2023-01-15T10:30:00+02:00', or a git date such as '1673771400 +0200'.",
+                date
+            )
+        })
+}
+
+pub fn validate_date(date: String) -> Result<(), String> {
+    parse_date(&date).map(|_| ())
+}
+
+fn parse_git_internal(date: &str) -> Option<Time> {
+    let mut parts = date.split_whitespace();
+    let seconds = parts.next()?;
+    let offset = parts.next()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    let seconds = seconds.strip_prefix('@').unwrap_or(seconds);
+    if seconds.is_empty() || !seconds.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(Time::new(seconds.parse().ok()?, parse_offset(offset)?))
+}
+
+fn parse_rfc3339(date: &str) -> Option<Time> {
+    if date.len() < 19 || !date.is_char_boundary(19) {
+        return None;
+    }
+    let (date_time, rest) = date.split_at(19);
+    let separators = date_time.as_bytes();
+    if separators[4] != b'-'
+        || separators[7] != b'-'
+        || !matches!(separators[10], b'T' | b't' | b' ')
+        || separators[13] != b':'
+        || separators[16] != b':'
+    {
+        return None;
+    }
+
+    let year = parse_digits(&date_time[0..4])?;
+    let month = parse_digits(&date_time[5..7])?;
+    let day = parse_digits(&date_time[8..10])?;
+    let hour = parse_digits(&date_time[11..13])?;
+    let minute = parse_digits(&date_time[14..16])?;
+    let second = parse_digits(&date_time[17..19])?;
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    // Git only stores whole seconds, so any fractional part is dropped.
+    let rest = match rest.strip_prefix('.') {
+        Some(fraction) => {
+            let fraction_len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+            if fraction_len == 0 {
+                return None;
+            }
+            &fraction[fraction_len..]
+        }
+        None => rest,
+    };
+    let offset_minutes = parse_offset(rest.trim_start())?;
+
+    let local_seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(Time::new(
+        local_seconds - i64::from(offset_minutes) * 60,
+        offset_minutes,
+    ))
+}
+
+/// Parses `Z`, `+hh:mm`, `+hhmm` or `+hh` (or their `-` equivalents) into minutes east of UTC.
+fn parse_offset(offset: &str) -> Option<i32> {
+    if offset == "Z" || offset == "z" {
+        return Some(0);
+    }
+    let sign = match offset.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let offset = &offset[1..];
+    let (hours, minutes) = match offset.len() {
+        2 => (offset, "00"),
+        4 => offset.split_at(2),
+        5 if offset.as_bytes()[2] == b':' => (&offset[0..2], &offset[3..5]),
+        _ => return None,
+    };
+    let hours = parse_digits(hours)?;
+    let minutes = parse_digits(minutes)?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes) as i32)
+}
+
+fn parse_digits(digits: &str) -> Option<i64> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Number of days between 1970-01-01 and the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Count years from March so that the leap day falls at the end of the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    #[test]
+    fn it_parses_rfc3339_dates_with_their_offset() {
+        let time = parse_date("2023-01-15T10:30:00+02:00").unwrap();
+        let signature = Signature::new("Author", "author@example.com", &time).unwrap();
+
+        assert_eq!(signature.when().seconds(), 1673771400);
+        assert_eq!(signature.when().offset_minutes(), 120);
+    }
+
+    #[test]
+    fn it_parses_negative_and_utc_offsets() {
+        let time = parse_date("1969-07-20 16:17:40 -04:00").unwrap();
+        assert_eq!(time.seconds(), -14182940);
+        assert_eq!(time.offset_minutes(), -240);
+        assert_eq!(time.sign(), '-');
+
+        let time = parse_date("2024-02-29T18:00:00.250Z").unwrap();
+        assert_eq!(time.seconds(), 1709229600);
+        assert_eq!(time.offset_minutes(), 0);
+    }
+
+    #[test]
+    fn it_parses_git_internal_dates() {
+        let time = parse_date("1673771400 +0530").unwrap();
+        assert_eq!(time.seconds(), 1673771400);
+        assert_eq!(time.offset_minutes(), 330);
+    }
+
+    #[test]
+    fn it_rejects_invalid_dates() {
+        for date in &[
+            "",
+            "yesterday",
+            "2023-01-15",
+            "2023-01-15T10:30:00",
+            "2023-02-29T10:30:00Z",
+            "2023-01-15T24:00:00Z",
+            "2023-01-15T10:30:00+2:00",
+            "1673771400",
+            "1673771400 0200",
+        ] {
+            assert!(parse_date(date).is_err(), "'{}' should be rejected", date);
+        }
+    }
+}
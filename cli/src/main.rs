@@ -1,7 +1,73 @@
-use clap::{crate_authors, crate_description, crate_name, crate_version, App, ArgGroup};
-use git2::{Commit, Repository, Signature};
-use git_regraph_lib::{CommitEdit, RefArg, RepositoryExt};
-use std::fs::read_to_string;
+mod date;
+mod spec;
+
+use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg, ArgGroup};
+use date::{parse_date, validate_date};
+use git2::{Commit, Repository, ResetType, Signature};
+use git_regraph_lib::{CommitEdit, RefArg, Regraph, RegraphError, RegraphReport};
+use spec::read_edit_spec;
+use std::{
+    fs::read_to_string,
+    io::{stdin, stdout, BufRead, Write},
+    path::Path,
+};
+
+fn new_signature(name: &str, email: &str, date: Option<&str>) -> Signature<'static> {
+    match date {
+        Some(date) => Signature::new(name, email, &parse_date(date).unwrap()).unwrap(),
+        None => Signature::now(name, email).unwrap(),
+    }
+}
+
+fn report_or_exit(
+    result: Result<RegraphReport, RegraphError>,
+    commit_to_edit: &Commit,
+) -> RegraphReport {
+    match result {
+        Err(RegraphError::NoChange) => {
+            println!("Nothing to do: the edit does not change {}.", commit_to_edit.id());
+            std::process::exit(0);
+        }
+        Err(RegraphError::NonFastForward { reference }) => {
+            eprintln!(
+                "error: refusing to rewind {}, as it would lose commits from its history. \
+                 Pass --force to allow this.",
+                reference
+            );
+            std::process::exit(1);
+        }
+        result => result.unwrap(),
+    }
+}
+
+/// Whether an answer to a yes/no prompt is a yes. Anything else, including the end of input, is
+/// taken as a no.
+fn read_confirmation(mut input: impl BufRead) -> bool {
+    let mut answer = String::new();
+    match input.read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+/// Asks whether to go ahead with the planned rewrite. Input that isn't a terminal is never taken
+/// as a yes, so that scripts have to pass --yes.
+fn confirm(plan: &RegraphReport) -> bool {
+    println!(
+        "This will rewrite {} commit(s) and move the following refs:",
+        plan.old_to_new_oids.len()
+    );
+    for name in &plan.updated_refs {
+        println!("    {}", name);
+    }
+    if !atty::is(atty::Stream::Stdin) {
+        eprintln!("error: stdin is not a terminal. Pass --yes to move refs without confirmation.");
+        return false;
+    }
+    print!("Continue? [y/N] ");
+    stdout().flush().unwrap();
+    read_confirmation(stdin().lock())
+}
 
 fn main() {
     let matches = App::new(crate_name!())
@@ -11,7 +77,7 @@ fn main() {
         .args_from_usage(
             "--update-all-local-refs     'Update all commits reachable from any non-remote ref, and update the non-remote refs to point to the updated commits.'
              --update-ref [ref] ...      'Update all commits reachable from this ref, and update this ref to point to these updated commits.'
-             <COMMIT>                    'A commit-ish revision specifier of the commit you would like to edit'
+             --edit-spec [SPEC]          'Read the COMMIT to edit and how to edit it from the TOML or JSON file SPEC, instead of from the command line'
              --keep-parents              'Leave the parents of the COMMIT unchanged'
              --clear-parents             'Remove all parents of the COMMIT'
              --parent [PARENT]...        'Specify a parent for the COMMIT.'
@@ -21,24 +87,55 @@ fn main() {
              --keep-tree                 'Leave the tree of the COMMIT unchanged'
              --tree [TREE]               'Specify an existing tree object id to override the tree of COMMIT'
              --keep-author               'Leave the author of the COMMIT unchanged'
-             --author [NAME] [email]     'Change the author of the COMMIT - updating the author time to now'
+             --author [NAME] [email]     'Change the author of the COMMIT - updating the author time to now unless --author-date is given'
              --keep-committer            'Leave the commiter of the COMMIT unchanged'
-             --committer [NAME] [email]  'Change the committer of the COMMIT - updating the commit time to now'
+             --committer [NAME] [email]  'Change the committer of the COMMIT - updating the commit time to now unless --committer-date is given'
+             --force                     'Move refs even if they would lose commits from their history, e.g. when squashing commits away'
+             -y, --yes                   'Move refs without asking for confirmation'
              "
         )
+        .arg(Arg::with_name("COMMIT")
+            .index(1)
+            .required_unless("edit-spec")
+            .conflicts_with("edit-spec")
+            .help("A commit-ish revision specifier of the commit you would like to edit"))
+        .arg(Arg::with_name("author-date")
+            .long("author-date")
+            .value_name("DATE")
+            .requires("author")
+            .validator(validate_date)
+            .help("Use DATE as the author time of the changed author, e.g. 2023-01-15T10:30:00+02:00 or '1673771400 +0200'"))
+        .arg(Arg::with_name("committer-date")
+            .long("committer-date")
+            .value_name("DATE")
+            .requires("committer")
+            .validator(validate_date)
+            .help("Use DATE as the commit time of the changed committer, e.g. 2023-01-15T10:30:00+02:00 or '1673771400 +0200'"))
+        .arg(Arg::with_name("keep-committer-date")
+            .long("keep-committer-date")
+            .requires("committer")
+            .conflicts_with("committer-date")
+            .help("Keep the original commit time of the COMMIT when changing its committer"))
+        .arg(Arg::with_name("reset-index")
+            .long("reset-index")
+            .help("Reset the index to the tree of the rewritten HEAD, as git commit --amend would"))
+        .arg(Arg::with_name("reset-hard")
+            .long("reset-hard")
+            .conflicts_with("reset-index")
+            .help("Reset both the index and the working tree to the tree of the rewritten HEAD, discarding any changes to them"))
         .group(ArgGroup::with_name("refs-to-update")
             .args(&["update-all-local-refs", "update-ref"]).required(true))
         .group(ArgGroup::with_name("parents-edit")
-            .args(&["keep-parents", "clear-parents", "parent"]).required(true))
+            .args(&["keep-parents", "clear-parents", "parent", "edit-spec"]).required(true))
         .group(ArgGroup::with_name("message-edit")
-            .args(&["keep-message", "message", "file"]).required(true))
+            .args(&["keep-message", "message", "file", "edit-spec"]).required(true))
         .group(ArgGroup::with_name("trees")
-            .args(&["keep-tree", "tree"]).required(true))
+            .args(&["keep-tree", "tree", "edit-spec"]).required(true))
 
         .group(ArgGroup::with_name("author-edit")
-            .args(&["keep-author", "author"]).required(true))
+            .args(&["keep-author", "author", "edit-spec"]).required(true))
         .group(ArgGroup::with_name("committer-edit")
-            .args(&["keep-committer", "committer"]).required(true))
+            .args(&["keep-committer", "committer", "edit-spec"]).required(true))
 
         .get_matches();
 
@@ -46,7 +143,7 @@ fn main() {
 
     let repo = Repository::open(std::env::current_dir().unwrap()).unwrap();
 
-    let refs_to_update = match (
+    let refs_to_update = || match (
         matches.is_present("update-all-local-refs"),
         matches.values_of("update-ref"),
     ) {
@@ -59,73 +156,188 @@ fn main() {
         _ => unreachable!(),
     };
 
+    let edit_spec = matches.value_of("edit-spec").map(|path| {
+        read_edit_spec(Path::new(path)).unwrap_or_else(|error| {
+            eprintln!("error: invalid edit spec {}: {}", path, error);
+            std::process::exit(1);
+        })
+    });
+
+    let commit_spec = match &edit_spec {
+        Some(spec) => spec.target.as_str(),
+        None => matches.value_of("COMMIT").unwrap(),
+    };
     let commit_to_edit = repo
-        .revparse_single(matches.value_of("COMMIT").unwrap())
+        .revparse_single(commit_spec)
         .unwrap()
         .into_commit()
         .expect("Specified COMMIT is not a commit");
 
     let mut edit = CommitEdit::new();
 
-    let parents_edit = matches
-        .is_present("clear-parents")
-        .then(Vec::new)
-        .or(matches.values_of("parent").map(|parents| {
-            parents
-                .map(|revspec| {
-                    repo.revparse_single(revspec)
-                        .unwrap()
-                        .into_commit()
-                        .expect("Specified PARENT is not a commit")
-                })
-                .collect::<Vec<Commit>>()
-        }));
+    let parent_specs: Option<Vec<&str>> = match &edit_spec {
+        Some(spec) => spec
+            .parents
+            .as_ref()
+            .map(|parents| parents.iter().map(String::as_str).collect()),
+        None => matches
+            .is_present("clear-parents")
+            .then(Vec::new)
+            .or_else(|| matches.values_of("parent").map(Iterator::collect)),
+    };
+    let parents_edit = parent_specs.map(|parents| {
+        parents
+            .into_iter()
+            .map(|revspec| {
+                repo.revparse_single(revspec)
+                    .unwrap()
+                    .into_commit()
+                    .expect("Specified PARENT is not a commit")
+            })
+            .collect::<Vec<Commit>>()
+    });
     let parent_refs;
     if let Some(parents) = &parents_edit {
         parent_refs = parents.iter().collect::<Vec<&Commit>>();
         edit.edit_parents(&parent_refs);
     }
 
-    let message_edit = matches
-        .values_of("message")
-        .map(|paragraphs| paragraphs.collect::<Vec<&str>>().join("\n\n"))
-        .or(matches
-            .value_of("file")
-            .map(|file| read_to_string(file).unwrap()));
+    let message_edit = match &edit_spec {
+        Some(spec) => spec.message.clone().or_else(|| {
+            spec.message_file.as_ref().map(|file| read_to_string(file).unwrap())
+        }),
+        None => matches
+            .values_of("message")
+            .map(|paragraphs| paragraphs.collect::<Vec<&str>>().join("\n\n"))
+            .or_else(|| matches.value_of("file").map(|file| read_to_string(file).unwrap())),
+    };
     if let Some(message) = &message_edit {
         edit.edit_message(message);
     }
 
-    let tree_edit = matches.value_of("tree").map(|tree_spec| {
-        repo.revparse_single(tree_spec)
-            .unwrap()
-            .into_tree()
-            .expect("Specifed TREE is not a tree")
-    });
-    if let Some(tree) = &tree_edit {
-        edit.edit_tree(tree);
+    let tree_spec = match &edit_spec {
+        Some(spec) => spec.tree.as_deref(),
+        None => matches.value_of("tree"),
+    };
+    if let Some(tree_spec) = tree_spec {
+        edit.edit_tree_oid(repo.revparse_single(tree_spec).unwrap().id());
     }
 
-    let author_edit = matches.values_of("author").map(|author_args| {
-        let author_info: Vec<_> = author_args.collect();
-        let name = author_info[0];
-        let email = author_info[1];
-        Signature::now(name, email).unwrap()
-    });
+    let author_info = match &edit_spec {
+        Some(spec) => spec.author.as_ref().map(|author| {
+            (
+                author.name.as_str(),
+                author.email.as_str(),
+                author.date.as_deref(),
+                author.keep_date,
+            )
+        }),
+        None => matches.values_of("author").map(|author_args| {
+            let author_info: Vec<_> = author_args.collect();
+            (
+                author_info[0],
+                author_info[1],
+                matches.value_of("author-date"),
+                false,
+            )
+        }),
+    };
+    let author_edit = author_info
+        .filter(|(_, _, _, keep_date)| !keep_date)
+        .map(|(name, email, date, _)| new_signature(name, email, date));
     if let Some(author) = &author_edit {
         edit.edit_author(author);
+    } else if let Some((name, email, _, _)) = author_info {
+        edit.edit_author_identity(name, email);
     }
 
-    let committer_edit = matches.values_of("committer").map(|committer_args| {
-        let committer_info: Vec<_> = committer_args.collect();
-        let name = committer_info[0];
-        let email = committer_info[1];
-        Signature::now(name, email).unwrap()
-    });
+    let committer_info = match &edit_spec {
+        Some(spec) => spec.committer.as_ref().map(|committer| {
+            (
+                committer.name.as_str(),
+                committer.email.as_str(),
+                committer.date.as_deref(),
+                committer.keep_date,
+            )
+        }),
+        None => matches.values_of("committer").map(|committer_args| {
+            let committer_info: Vec<_> = committer_args.collect();
+            (
+                committer_info[0],
+                committer_info[1],
+                matches.value_of("committer-date"),
+                matches.is_present("keep-committer-date"),
+            )
+        }),
+    };
+    let committer_edit = committer_info
+        .filter(|(_, _, _, keep_date)| !keep_date)
+        .map(|(name, email, date, _)| new_signature(name, email, date));
     if let Some(committer) = &committer_edit {
         edit.edit_committer(committer);
+    } else if let Some((name, email, _, _)) = committer_info {
+        edit.edit_committer_identity(name, email);
+    }
+
+    let mut regraph = Regraph::new(&repo);
+    regraph.allow_non_fast_forward(matches.is_present("force"));
+    if matches.is_present("reset-hard") {
+        regraph.reset_head(ResetType::Hard);
+    } else if matches.is_present("reset-index") {
+        regraph.reset_head(ResetType::Mixed);
     }
 
-    repo.regraph(refs_to_update, &commit_to_edit, &edit)
-        .unwrap();
+    let report = if matches.is_present("yes") {
+        report_or_exit(
+            regraph.run(refs_to_update(), &commit_to_edit, &edit),
+            &commit_to_edit,
+        )
+    } else {
+        regraph.dry_run(true);
+        let plan = report_or_exit(
+            regraph.run(refs_to_update(), &commit_to_edit, &edit),
+            &commit_to_edit,
+        );
+        if !confirm(&plan) {
+            eprintln!("Aborted: no refs were moved.");
+            std::process::exit(1);
+        }
+        regraph.dry_run(false);
+        report_or_exit(
+            regraph.apply(refs_to_update(), &commit_to_edit, &plan),
+            &commit_to_edit,
+        )
+    };
+    for warning in &report.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if report.rewritten_descendants == 0 {
+        println!("No descendant commits needed rewriting.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_only_confirms_a_yes() {
+        for (input, confirmed) in &[
+            ("y\n", true),
+            ("yes\n", true),
+            (" YES \n", true),
+            ("n\n", false),
+            ("no\n", false),
+            ("yep\n", false),
+            ("\n", false),
+            ("", false),
+        ] {
+            assert_eq!(
+                read_confirmation(input.as_bytes()),
+                *confirmed,
+                "Answering {:?}",
+                input
+            );
+        }
+    }
 }
@@ -0,0 +1,262 @@
+use crate::date::parse_date;
+use serde::Deserialize;
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// An edit described in a file, so that it can be reviewed alongside other changes and replayed,
+/// e.g. in CI.
+///
+/// The file is TOML, or JSON if its name ends in `.json`, with the same keys either way. Anything
+/// left out is kept as it is, much like the `--keep-*` options.
+///
+/// ```toml
+/// target = "HEAD~2"
+/// parents = ["HEAD~4"] # Or [] to remove all parents.
+/// message = """
+/// Subject
+///
+/// Body
+/// """
+/// tree = "HEAD^{tree}"
+///
+/// [author]
+/// name = "Author"
+/// email = "author@example.com"
+/// date = "2023-01-15T10:30:00+02:00"
+///
+/// [committer]
+/// name = "Committer"
+/// email = "committer@example.com"
+/// keep-date = true
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct EditSpec {
+    /// A commit-ish revision specifier of the commit to edit.
+    pub target: String,
+    pub parents: Option<Vec<String>>,
+    pub message: Option<String>,
+    /// A file to source the message from. Once read by `read_edit_spec`, a relative path has
+    /// been resolved against the directory of the spec file.
+    pub message_file: Option<PathBuf>,
+    pub tree: Option<String>,
+    pub author: Option<IdentitySpec>,
+    pub committer: Option<IdentitySpec>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct IdentitySpec {
+    pub name: String,
+    pub email: String,
+    pub date: Option<String>,
+    /// Keep the original time of the signature, instead of setting it to `date` or to now.
+    #[serde(default)]
+    pub keep_date: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SpecFormat {
+    Toml,
+    Json,
+}
+
+impl SpecFormat {
+    /// JSON for files ending in `.json`, and TOML otherwise.
+    fn of(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension == "json" => SpecFormat::Json,
+            _ => SpecFormat::Toml,
+        }
+    }
+}
+
+pub fn read_edit_spec(path: &Path) -> Result<EditSpec, String> {
+    let spec = read_to_string(path).map_err(|error| error.to_string())?;
+    let mut edit_spec = parse_edit_spec(&spec, SpecFormat::of(path))?;
+    if let (Some(message_file), Some(spec_dir)) = (&edit_spec.message_file, path.parent()) {
+        edit_spec.message_file = Some(spec_dir.join(message_file));
+    }
+    Ok(edit_spec)
+}
+
+fn parse_edit_spec(spec: &str, format: SpecFormat) -> Result<EditSpec, String> {
+    let edit_spec: EditSpec = match format {
+        SpecFormat::Toml => toml::from_str(spec).map_err(|error| error.to_string())?,
+        SpecFormat::Json => serde_json::from_str(spec).map_err(|error| error.to_string())?,
+    };
+
+    if edit_spec.message.is_some() && edit_spec.message_file.is_some() {
+        return Err(conflict("message", "message-file"));
+    }
+    if let Some(author) = &edit_spec.author {
+        check_identity("author", author)?;
+    }
+    if let Some(committer) = &edit_spec.committer {
+        check_identity("committer", committer)?;
+    }
+    Ok(edit_spec)
+}
+
+fn check_identity(table: &str, identity: &IdentitySpec) -> Result<(), String> {
+    if identity.keep_date && identity.date.is_some() {
+        return Err(conflict(&format!("{}.keep-date", table), &format!("{}.date", table)));
+    }
+    if let Some(date) = &identity.date {
+        parse_date(date)?;
+    }
+    Ok(())
+}
+
+fn conflict(key: &str, other_key: &str) -> String {
+    format!("'{}' cannot be used with '{}'.", key, other_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_edit_spec() -> EditSpec {
+        EditSpec {
+            target: "HEAD~2".to_owned(),
+            parents: Some(vec!["HEAD~4".to_owned(), "feature".to_owned()]),
+            message: Some("Subject\n\nBody with \"quotes\"\n".to_owned()),
+            message_file: None,
+            tree: Some("HEAD^{tree}".to_owned()),
+            author: Some(IdentitySpec {
+                name: "Author".to_owned(),
+                email: "author@example.com".to_owned(),
+                date: Some("2023-01-15T10:30:00+02:00".to_owned()),
+                keep_date: false,
+            }),
+            committer: Some(IdentitySpec {
+                name: "Committer".to_owned(),
+                email: "committer@example.com".to_owned(),
+                date: None,
+                keep_date: true,
+            }),
+        }
+    }
+
+    #[test]
+    fn it_parses_a_full_edit_spec() {
+        let spec = parse_edit_spec(
+            "# Reword and reparent the commit.\n\
+             target = \"HEAD~2\"\n\
+             parents = [\"HEAD~4\", \"feature\"] # Two parents.\n\
+             message = \"\"\"\n\
+             Subject\n\
+             \n\
+             Body with \\\"quotes\\\"\n\
+             \"\"\"\n\
+             tree = \"HEAD^{tree}\"\n\
+             \n\
+             [author]\n\
+             name = \"Author\"\n\
+             email = \"author@example.com\"\n\
+             date = \"2023-01-15T10:30:00+02:00\"\n\
+             \n\
+             [committer]\n\
+             name = \"Committer\"\n\
+             email = \"committer@example.com\"\n\
+             keep-date = true\n",
+            SpecFormat::Toml,
+        )
+        .unwrap();
+
+        assert_eq!(spec, full_edit_spec());
+    }
+
+    #[test]
+    fn it_parses_a_full_json_edit_spec() {
+        let spec = parse_edit_spec(
+            r#"{
+                "target": "HEAD~2",
+                "parents": ["HEAD~4", "feature"],
+                "message": "Subject\n\nBody with \"quotes\"\n",
+                "tree": "HEAD^{tree}",
+                "author": {
+                    "name": "Author",
+                    "email": "author@example.com",
+                    "date": "2023-01-15T10:30:00+02:00"
+                },
+                "committer": {
+                    "name": "Committer",
+                    "email": "committer@example.com",
+                    "keep-date": true
+                }
+            }"#,
+            SpecFormat::Json,
+        )
+        .unwrap();
+
+        assert_eq!(spec, full_edit_spec());
+    }
+
+    #[test]
+    fn it_keeps_everything_left_out() {
+        let spec = parse_edit_spec("target = \"HEAD\"\nparents = []\n", SpecFormat::Toml).unwrap();
+
+        assert_eq!(
+            spec,
+            EditSpec {
+                target: "HEAD".to_owned(),
+                parents: Some(Vec::new()),
+                ..EditSpec::default()
+            }
+        );
+    }
+
+    #[test]
+    fn it_resolves_the_message_file_against_the_spec_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spec.json");
+        std::fs::write(&path, r#"{"target": "HEAD", "message-file": "message.txt"}"#).unwrap();
+
+        let spec = read_edit_spec(&path).unwrap();
+
+        assert_eq!(spec.message_file, Some(dir.path().join("message.txt")));
+    }
+
+    #[test]
+    fn it_rejects_conflicting_options() {
+        for spec in &[
+            "target = \"HEAD\"\nmessage = \"A\"\nmessage-file = \"a.txt\"\n",
+            "target = \"HEAD\"\n[committer]\nname = \"C\"\nemail = \"c\"\ndate = \"0 +0000\"\n\
+             keep-date = true\n",
+            "target = \"HEAD\"\n[author]\nname = \"A\"\nemail = \"a\"\ndate = \"0 +0000\"\n\
+             keep-date = true\n",
+        ] {
+            let error = parse_edit_spec(spec, SpecFormat::Toml).unwrap_err();
+            assert!(
+                error.contains("cannot be used with"),
+                "'{}' should be rejected as conflicting, got: {}",
+                spec,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_edit_specs() {
+        for spec in &[
+            "",
+            "message = \"No target\"\n",
+            "target = \"HEAD\"\ntarget = \"HEAD~\"\n",
+            "target = \"HEAD\"\nunknown = \"value\"\n",
+            "target = \"HEAD\"\nparents = \"HEAD~\"\n",
+            "target = \"HEAD\" trailing\n",
+            "target = \"HEAD\n",
+            "target = \"HEAD\"\n[author]\nname = \"A\"\n",
+            "target = \"HEAD\"\n[author]\nname = \"A\"\nemail = \"a\"\ndate = \"yesterday\"\n",
+        ] {
+            assert!(
+                parse_edit_spec(spec, SpecFormat::Toml).is_err(),
+                "'{}' should be rejected",
+                spec
+            );
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use git2::{Oid, Repository, Signature, Time};
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+use tempfile::{tempdir, TempDir};
+
+/// Creates a repo with a linear history, with master checked out at the last commit.
+fn given_linear_repository(labels: &[&str]) -> (Repository, Vec<Oid>, TempDir) {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(&dir).unwrap();
+    let tree_oid = repo.treebuilder(None).unwrap().write().unwrap();
+    let mut oids: Vec<Oid> = Vec::new();
+    {
+        let tree = repo.find_tree(tree_oid).unwrap();
+        for (time, label) in labels.iter().enumerate() {
+            let email = format!("{}-email", label);
+            let signature = Signature::new(label, &email, &Time::new(time as i64, 0)).unwrap();
+            let parents: Vec<_> = oids
+                .last()
+                .map(|oid| repo.find_commit(*oid).unwrap())
+                .into_iter()
+                .collect();
+            let parent_refs: Vec<_> = parents.iter().collect();
+            let oid = repo
+                .commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    label,
+                    &tree,
+                    &parent_refs,
+                )
+                .unwrap();
+            oids.push(oid);
+        }
+    }
+    (repo, oids, dir)
+}
+
+fn run_regraph(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-regraph"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn run_regraph_with_input(dir: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_git-regraph"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn master_target(repo: &Repository) -> Oid {
+    repo.find_reference("refs/heads/master")
+        .unwrap()
+        .target()
+        .unwrap()
+}
+
+const SQUASH_INTO_HEAD: &[&str] = &[
+    "--update-all-local-refs",
+    "HEAD",
+    "--clear-parents",
+    "--keep-message",
+    "--keep-tree",
+    "--keep-author",
+    "--keep-committer",
+    "--yes",
+];
+
+const EDIT_B_MESSAGE: &[&str] = &[
+    "--update-all-local-refs",
+    "HEAD~",
+    "--keep-parents",
+    "--message",
+    "Edited B",
+    "--keep-tree",
+    "--keep-author",
+    "--keep-committer",
+];
+
+#[test]
+fn it_refuses_to_rewind_refs_without_force() {
+    // GIVEN a repo with a linear history.
+    let (repo, oids, dir) = given_linear_repository(&["A", "B", "C"]);
+
+    // WHEN we squash everything into C without --force.
+    let output = run_regraph(dir.path(), SQUASH_INTO_HEAD);
+
+    // THEN the CLI refuses, naming the ref that would be rewound.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "The CLI should fail");
+    assert!(
+        stderr.contains("refs/heads/master"),
+        "The error should name the rewound ref, got: {}",
+        stderr
+    );
+
+    // THEN master is left untouched.
+    assert_eq!(master_target(&repo), oids[2]);
+}
+
+#[test]
+fn it_rewinds_refs_with_force() {
+    // GIVEN a repo with a linear history.
+    let (repo, oids, dir) = given_linear_repository(&["A", "B", "C"]);
+
+    // WHEN we squash everything into C with --force.
+    let mut args = SQUASH_INTO_HEAD.to_vec();
+    args.push("--force");
+    let output = run_regraph(dir.path(), &args);
+
+    // THEN the CLI succeeds.
+    assert!(
+        output.status.success(),
+        "The CLI should succeed, got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // THEN master points to a rewritten C without any parents.
+    let new_c = repo.find_commit(master_target(&repo)).unwrap();
+    assert_ne!(new_c.id(), oids[2]);
+    assert_eq!(new_c.message(), Some("C"));
+    assert_eq!(new_c.parent_count(), 0);
+}
+
+#[test]
+fn it_does_not_take_piped_input_as_confirmation() {
+    // GIVEN a repo with a linear history.
+    let (repo, oids, dir) = given_linear_repository(&["A", "B", "C"]);
+
+    // WHEN we edit B without --yes, piping in a yes.
+    let output = run_regraph_with_input(dir.path(), EDIT_B_MESSAGE, "y\n");
+
+    // THEN the CLI shows the refs that would move, and fails, as stdin is not a terminal.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("refs/heads/master"),
+        "The plan should name the refs to move, got: {}",
+        stdout
+    );
+    assert!(!output.status.success(), "The CLI should fail");
+
+    // THEN master is left untouched.
+    assert_eq!(master_target(&repo), oids[2]);
+}
+
+#[test]
+fn it_moves_refs_without_confirmation_when_passed_yes() {
+    // GIVEN a repo with a linear history.
+    let (repo, oids, dir) = given_linear_repository(&["A", "B", "C"]);
+
+    // WHEN we edit B with --yes, without any input.
+    let mut args = EDIT_B_MESSAGE.to_vec();
+    args.push("--yes");
+    let output = run_regraph(dir.path(), &args);
+
+    // THEN the CLI succeeds.
+    assert!(
+        output.status.success(),
+        "The CLI should succeed, got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // THEN master points to a rewritten C based on the edited B.
+    let new_c = repo.find_commit(master_target(&repo)).unwrap();
+    assert_ne!(new_c.id(), oids[2]);
+    assert_eq!(new_c.parent(0).unwrap().message(), Some("Edited B"));
+}
+
+#[test]
+fn it_applies_an_edit_spec() {
+    // GIVEN a repo with a linear history.
+    let (repo, oids, dir) = given_linear_repository(&["A", "B", "C"]);
+    // With a spec for editing B.
+    std::fs::write(
+        dir.path().join("spec.toml"),
+        "target = \"HEAD~\"\n\
+         message = \"\"\"\n\
+         Edited B\n\
+         \n\
+         From a spec.\n\
+         \"\"\"\n\
+         \n\
+         [author]\n\
+         name = \"Spec Author\"\n\
+         email = \"spec@example.com\"\n\
+         date = \"2023-01-15T10:30:00+02:00\"\n",
+    )
+    .unwrap();
+
+    // WHEN we run the edit from the spec.
+    let output = run_regraph(
+        dir.path(),
+        &["--update-all-local-refs", "--edit-spec", "spec.toml", "--yes"],
+    );
+
+    // THEN the CLI succeeds.
+    assert!(
+        output.status.success(),
+        "The CLI should succeed, got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // THEN B is edited as described by the spec, keeping everything the spec leaves out.
+    let new_c = repo.find_commit(master_target(&repo)).unwrap();
+    assert_ne!(new_c.id(), oids[2]);
+    let new_b = new_c.parent(0).unwrap();
+    assert_eq!(new_b.message(), Some("Edited B\n\nFrom a spec.\n"));
+    assert_eq!(new_b.author().name(), Some("Spec Author"));
+    assert_eq!(new_b.author().email(), Some("spec@example.com"));
+    assert_eq!(new_b.author().when().seconds(), 1673771400);
+    assert_eq!(new_b.committer().name(), Some("B"));
+    assert_eq!(new_b.parent_id(0).unwrap(), oids[0]);
+}
+
+#[test]
+fn it_keeps_the_author_date_from_an_edit_spec() {
+    // GIVEN a repo with a linear history.
+    let (repo, _, dir) = given_linear_repository(&["A", "B", "C"]);
+    // With a spec renaming the author of B, keeping its date.
+    std::fs::write(
+        dir.path().join("spec.toml"),
+        "target = \"HEAD~\"\n\
+         \n\
+         [author]\n\
+         name = \"Spec Author\"\n\
+         email = \"spec@example.com\"\n\
+         keep-date = true\n",
+    )
+    .unwrap();
+
+    // WHEN we run the edit from the spec.
+    let output = run_regraph(
+        dir.path(),
+        &["--update-all-local-refs", "--edit-spec", "spec.toml", "--yes"],
+    );
+
+    // THEN the CLI succeeds.
+    assert!(
+        output.status.success(),
+        "The CLI should succeed, got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // THEN B has the new author, at its original date.
+    let new_b = repo.find_commit(master_target(&repo)).unwrap().parent(0).unwrap();
+    assert_eq!(new_b.author().name(), Some("Spec Author"));
+    assert_eq!(new_b.author().email(), Some("spec@example.com"));
+    assert_eq!(new_b.author().when().seconds(), 1);
+}
+
+#[test]
+fn it_reads_a_json_edit_spec_with_a_message_file_next_to_it() {
+    // GIVEN a repo with a linear history.
+    let (repo, oids, dir) = given_linear_repository(&["A", "B", "C"]);
+    // With a JSON spec for editing B, in a subdirectory along with its message file.
+    let spec_dir = dir.path().join("specs");
+    std::fs::create_dir(&spec_dir).unwrap();
+    std::fs::write(spec_dir.join("message.txt"), "Edited B\n").unwrap();
+    std::fs::write(
+        spec_dir.join("spec.json"),
+        r#"{"target": "HEAD~", "message-file": "message.txt"}"#,
+    )
+    .unwrap();
+
+    // WHEN we run the edit from the spec, from the root of the repo.
+    let output = run_regraph(
+        dir.path(),
+        &["--update-all-local-refs", "--edit-spec", "specs/spec.json", "--yes"],
+    );
+
+    // THEN the CLI succeeds.
+    assert!(
+        output.status.success(),
+        "The CLI should succeed, got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // THEN B takes its message from the file next to the spec.
+    let new_b = repo.find_commit(master_target(&repo)).unwrap().parent(0).unwrap();
+    assert_eq!(new_b.message(), Some("Edited B\n"));
+    assert_eq!(new_b.parent_id(0).unwrap(), oids[0]);
+}
+
+#[test]
+fn it_rejects_an_edit_spec_combined_with_edit_options() {
+    // GIVEN a repo with a linear history.
+    let (repo, oids, dir) = given_linear_repository(&["A", "B", "C"]);
+    // With a spec for editing B.
+    std::fs::write(dir.path().join("spec.toml"), "target = \"HEAD~\"\n").unwrap();
+
+    // WHEN we run the edit from the spec, along with an option the spec already covers.
+    let output = run_regraph(
+        dir.path(),
+        &[
+            "--update-all-local-refs",
+            "--edit-spec",
+            "spec.toml",
+            "--keep-message",
+            "--yes",
+        ],
+    );
+
+    // THEN the CLI fails, and master is left untouched.
+    assert!(!output.status.success(), "The CLI should fail");
+    assert_eq!(master_target(&repo), oids[2]);
+}
+
+#[test]
+fn it_resets_the_index_of_a_rewritten_head() {
+    // GIVEN a repo with a linear history.
+    let (repo, oids, dir) = given_linear_repository(&["A", "B", "C"]);
+    // With a tree containing a file, to replace the tree of C.
+    let blob_oid = repo.blob(b"content\n").unwrap();
+    let mut tree_builder = repo.treebuilder(None).unwrap();
+    tree_builder.insert("file", blob_oid, 0o100644).unwrap();
+    let tree_oid = tree_builder.write().unwrap();
+
+    // WHEN we change the tree of HEAD with --reset-index.
+    let tree_arg = tree_oid.to_string();
+    let output = run_regraph(
+        dir.path(),
+        &[
+            "--update-all-local-refs",
+            "HEAD",
+            "--keep-parents",
+            "--keep-message",
+            "--tree",
+            &tree_arg,
+            "--keep-author",
+            "--keep-committer",
+            "--reset-index",
+            "--yes",
+        ],
+    );
+
+    // THEN the CLI succeeds.
+    assert!(
+        output.status.success(),
+        "The CLI should succeed, got: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // THEN HEAD is rewritten with the new tree, and the index matches it.
+    let new_c = repo.find_commit(master_target(&repo)).unwrap();
+    assert_ne!(new_c.id(), oids[2]);
+    assert_eq!(new_c.tree_id(), tree_oid);
+    let mut index = Repository::open(dir.path()).unwrap().index().unwrap();
+    assert_eq!(index.write_tree().unwrap(), tree_oid);
+}
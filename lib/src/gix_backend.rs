@@ -0,0 +1,95 @@
+//! Reads and writes the commits being rewritten with the pure-Rust gix crate instead of libgit2.
+
+use crate::{OriginalCommit, RegraphError};
+use git2::{Oid, Signature, Time};
+use std::{backtrace::Backtrace, path::Path};
+
+pub(crate) struct Objects {
+    repo: gix::Repository,
+}
+
+impl Objects {
+    pub(crate) fn open(git_dir: &Path) -> Result<Self, RegraphError> {
+        let repo = gix::open(git_dir).map_err(gix_error)?;
+        Ok(Objects { repo })
+    }
+
+    pub(crate) fn find_commit(&self, oid: Oid) -> Result<OriginalCommit, RegraphError> {
+        let commit = self
+            .repo
+            .find_object(object_id(oid))
+            .map_err(gix_error)?
+            .try_into_commit()
+            .map_err(gix_error)?;
+        let decoded = commit.decode().map_err(gix_error)?;
+        // The header ends at the first empty line, which separates it from the message.
+        let header_end = commit
+            .data
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .map_or(commit.data.len(), |newline| newline + 1);
+        let message = &decoded.message[..];
+        let message_start = message
+            .iter()
+            .position(|byte| *byte != b'\n')
+            .unwrap_or_else(|| message.len());
+        Ok(OriginalCommit {
+            id: oid,
+            tree_id: git2_oid(&decoded.tree())?,
+            parent_ids: decoded
+                .parents()
+                .map(|parent| git2_oid(&parent))
+                .collect::<Result<_, _>>()?,
+            author: signature(&decoded.author, oid)?,
+            committer: signature(&decoded.committer, oid)?,
+            encoding: decoded
+                .encoding
+                .map(|encoding| String::from_utf8_lossy(encoding).into_owned()),
+            raw_header: commit.data[..header_end].to_vec(),
+            message: message[message_start..].to_vec(),
+        })
+    }
+
+    pub(crate) fn is_tree(&self, oid: Oid) -> bool {
+        self.repo
+            .find_object(object_id(oid))
+            .map_or(false, |object| object.kind == gix::object::Kind::Tree)
+    }
+
+    pub(crate) fn write_commit(&self, buffer: &[u8]) -> Result<Oid, RegraphError> {
+        let id = self
+            .repo
+            .write_buf(gix::object::Kind::Commit, buffer)
+            .map_err(gix_error)?;
+        git2_oid(&id)
+    }
+}
+
+fn object_id(oid: Oid) -> gix::ObjectId {
+    gix::ObjectId::from_bytes_or_panic(oid.as_bytes())
+}
+
+fn git2_oid(id: &gix::oid) -> Result<Oid, RegraphError> {
+    Ok(Oid::from_bytes(id.as_bytes())?)
+}
+
+fn signature(
+    signature: &gix::actor::SignatureRef,
+    commit: Oid,
+) -> Result<Signature<'static>, RegraphError> {
+    let invalid_utf8 = || RegraphError::CommitWithInvalidUtf8Signature {
+        commit,
+        backtrace: Backtrace::capture(),
+    };
+    let name = std::str::from_utf8(signature.name).map_err(|_| invalid_utf8())?;
+    let email = std::str::from_utf8(signature.email).map_err(|_| invalid_utf8())?;
+    let time = Time::new(signature.time.seconds, signature.time.offset / 60);
+    Ok(Signature::new(name, email, &time)?)
+}
+
+fn gix_error(error: impl std::error::Error + Send + Sync + 'static) -> RegraphError {
+    RegraphError::GixError {
+        source: Box::new(error),
+        backtrace: Backtrace::capture(),
+    }
+}
@@ -1,7 +1,16 @@
 #![feature(backtrace)]
 
-use git2::{Commit, Oid, Reference, Repository, Signature, Sort, Tree};
-use std::{backtrace::Backtrace, collections::HashMap};
+#[cfg(feature = "gix-backend")]
+mod gix_backend;
+
+use git2::{
+    Buf, Commit, Mailmap, ObjectType, Oid, Reference, Repository, ResetType, Signature, Sort, Tree,
+};
+use std::{
+    backtrace::Backtrace,
+    collections::{HashMap, HashSet},
+    io::Write,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,19 +21,379 @@ pub enum RegraphError {
         source: git2::Error,
         backtrace: Backtrace,
     },
+    #[error("Failed to write objects")]
+    IoError {
+        #[from]
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
     #[error("Commit {commit} does not have a valid utf-8 message and could not be re-applied.")]
     CommitWithInvalidUtf8Message { commit: Oid, backtrace: Backtrace },
+    #[error("Commit {commit} does not have a valid utf-8 author or committer email and could not be re-applied.")]
+    CommitWithInvalidUtf8Signature { commit: Oid, backtrace: Backtrace },
     #[error("The specified edit specification does not actually change the commit.")]
     NoChange,
+    #[error("Reference {reference} would lose commits from its history. Allow non-fast-forward updates to proceed anyway.")]
+    NonFastForward { reference: String },
+    #[error("Commit {commit} is not reachable from any of the refs being updated.")]
+    CommitNotReachable { commit: Oid },
+    #[error("Object {oid} is not a tree and cannot be used as the tree of the edited commit.")]
+    NotATree { oid: Oid, backtrace: Backtrace },
+    #[error("Ref {reference} can still reach commit {commit}, which was supposed to be rewritten.")]
+    StaleCommit { reference: String, commit: Oid },
+    #[error("Ref {reference} does not resolve to a commit.")]
+    UnresolvableReference { reference: String },
+    #[error("Failed to update ref {reference}.")]
+    RefUpdateFailed {
+        reference: String,
+        source: git2::Error,
+    },
+    #[error("Object {oid} is no longer in the object database.")]
+    MissingObject { oid: Oid },
+    #[error("Ref {reference} points to commit {commit}, which several commits were rewritten into.")]
+    AmbiguousUndo { reference: String, commit: Oid },
+    #[error("Ref {reference} points to commit {commit}, which existed before the regraph, so it is not known whether the regraph moved the ref there.")]
+    PreexistingUndoTarget { reference: String, commit: Oid },
+    #[cfg(feature = "gix-backend")]
+    #[error("Failed to read or write objects with gix")]
+    GixError {
+        source: Box<dyn std::error::Error + Send + Sync>,
+        backtrace: Backtrace,
+    },
+}
+
+const STASH_REF: &str = "refs/stash";
+const DEFAULT_REFLOG_MESSAGE: &str = "regraph: update after editing commit {old} -> {new}";
+
+fn reference_name(reference: &Reference) -> String {
+    String::from_utf8_lossy(reference.name_bytes()).into_owned()
+}
+
+/// Follows a ref through any symbolic refs to the direct ref it ends at, and that ref's target.
+fn resolve_reference<'r>(reference: &Reference<'r>) -> Result<(Reference<'r>, Oid), RegraphError> {
+    let unresolvable = || RegraphError::UnresolvableReference {
+        reference: reference_name(reference),
+    };
+    let direct_ref = match reference.resolve() {
+        Ok(direct_ref) => direct_ref,
+        Err(error) if error.code() == git2::ErrorCode::NotFound => return Err(unresolvable()),
+        Err(error) => return Err(error.into()),
+    };
+    let target = direct_ref.target().ok_or_else(unresolvable)?;
+    Ok((direct_ref, target))
+}
+
+fn write_signature(buffer: &mut Vec<u8>, field: &str, signature: &Signature) {
+    let when = signature.when();
+    let offset = when.offset_minutes();
+    let sign = if offset < 0 || when.sign() == '-' {
+        '-'
+    } else {
+        '+'
+    };
+    buffer.extend_from_slice(field.as_bytes());
+    buffer.push(b' ');
+    buffer.extend_from_slice(signature.name_bytes());
+    buffer.extend_from_slice(b" <");
+    buffer.extend_from_slice(signature.email_bytes());
+    buffer.extend_from_slice(
+        format!(
+            "> {} {}{:02}{:02}\n",
+            when.seconds(),
+            sign,
+            offset.abs() / 60,
+            offset.abs() % 60
+        )
+        .as_bytes(),
+    );
+}
+
+/// Headers that sign the exact content of the original commit, which no longer verify once it
+/// has been rewritten, so they are dropped.
+///
+/// A commit is only rewritten when its content changes: either its parents were rewritten, so
+/// the signature covers the old parents, or its tree, message or identities were edited. A
+/// commit whose content would come out the same is kept as it is, signature and all, rather than
+/// being rewritten.
+const SIGNATURE_HEADERS: &[&str] = &["gpgsig", "gpgsig-sha256"];
+
+/// A commit being rewritten, as read by whichever backend writes its replacement.
+struct OriginalCommit {
+    id: Oid,
+    tree_id: Oid,
+    parent_ids: Vec<Oid>,
+    author: Signature<'static>,
+    committer: Signature<'static>,
+    encoding: Option<String>,
+    /// The header lines of the commit object, as returned by `Commit::raw_header_bytes`.
+    raw_header: Vec<u8>,
+    /// The message, without any leading newlines, as returned by `Commit::message_bytes`.
+    message: Vec<u8>,
+}
+
+impl OriginalCommit {
+    fn from_git2(commit: &Commit) -> Self {
+        OriginalCommit {
+            id: commit.id(),
+            tree_id: commit.tree_id(),
+            parent_ids: commit.parent_ids().collect(),
+            author: commit.author().to_owned(),
+            committer: commit.committer().to_owned(),
+            encoding: commit.message_encoding().map(str::to_owned),
+            raw_header: commit.raw_header_bytes().to_vec(),
+            message: commit.message_bytes().to_vec(),
+        }
+    }
+
+    fn message(&self) -> Option<&str> {
+        std::str::from_utf8(&self.message).ok()
+    }
+}
+
+/// Reads the commits being rewritten and writes their replacements. Refs and revwalks always go
+/// through libgit2.
+enum ObjectBackend<'r> {
+    Git2(&'r Repository),
+    #[cfg(feature = "gix-backend")]
+    Gix(gix_backend::Objects),
+}
+
+impl ObjectBackend<'_> {
+    fn find_commit(&self, oid: Oid) -> Result<OriginalCommit, RegraphError> {
+        match self {
+            ObjectBackend::Git2(repo) => Ok(OriginalCommit::from_git2(&repo.find_commit(oid)?)),
+            #[cfg(feature = "gix-backend")]
+            ObjectBackend::Gix(objects) => objects.find_commit(oid),
+        }
+    }
+
+    /// Whether the object exists and is a tree.
+    fn is_tree(&self, oid: Oid) -> bool {
+        match self {
+            ObjectBackend::Git2(repo) => repo
+                .find_object(oid, None)
+                .map_or(false, |object| object.kind() == Some(ObjectType::Tree)),
+            #[cfg(feature = "gix-backend")]
+            ObjectBackend::Gix(objects) => objects.is_tree(oid),
+        }
+    }
+
+    fn write_commit(&self, buffer: &[u8]) -> Result<Oid, RegraphError> {
+        match self {
+            ObjectBackend::Git2(repo) => Ok(repo.odb()?.write(ObjectType::Commit, buffer)?),
+            #[cfg(feature = "gix-backend")]
+            ObjectBackend::Gix(objects) => objects.write_commit(buffer),
+        }
+    }
+}
+
+/// The contents of a commit object, written in the same format as `Repository::commit`, except
+/// that it can also carry the `encoding` header of the message and any other headers of the
+/// original commit, such as `mergetag`, which `Repository::commit` always omits.
+pub struct CommitBuffer<'a> {
+    pub tree_id: Oid,
+    pub parent_ids: Vec<Oid>,
+    pub author: Signature<'a>,
+    pub committer: Signature<'a>,
+    pub encoding: Option<&'a str>,
+    /// Headers written after all of the above, in order, as pairs of keys and values. Lines after
+    /// the first of a multi-line value are separated by newlines alone.
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    pub message: &'a [u8],
+}
+
+impl CommitBuffer<'_> {
+    fn write(&self, objects: &ObjectBackend) -> Result<Oid, RegraphError> {
+        let mut buffer = format!("tree {}\n", self.tree_id).into_bytes();
+        for parent_id in &self.parent_ids {
+            buffer.extend_from_slice(format!("parent {}\n", parent_id).as_bytes());
+        }
+        write_signature(&mut buffer, "author", &self.author);
+        write_signature(&mut buffer, "committer", &self.committer);
+        if let Some(encoding) = self.encoding {
+            buffer.extend_from_slice(format!("encoding {}\n", encoding).as_bytes());
+        }
+        for (key, value) in &self.extra_headers {
+            buffer.extend_from_slice(key.as_bytes());
+            buffer.push(b' ');
+            // Each line after the first of a multi-line value is marked by a leading space.
+            for byte in value {
+                buffer.push(*byte);
+                if *byte == b'\n' {
+                    buffer.push(b' ');
+                }
+            }
+            buffer.push(b'\n');
+        }
+        buffer.push(b'\n');
+        buffer.extend_from_slice(self.message);
+        objects.write_commit(&buffer)
+    }
+
+    /// Whether this has the same content as the given commit, in which case writing it would
+    /// only drop any signature of the commit, so the commit should be kept as it is instead.
+    fn matches(&self, commit: &OriginalCommit) -> bool {
+        self.tree_id == commit.tree_id
+            && self.parent_ids == commit.parent_ids
+            && same_signature(&self.author, &commit.author)
+            && same_signature(&self.committer, &commit.committer)
+            && self.encoding == commit.encoding.as_deref()
+            && self.message == commit.message.as_slice()
+    }
+}
+
+/// The parents of a commit, replaced by their rewritten counterparts. Parents keep their
+/// original order, as it is stored in the commit, so that the first parent stays first.
+fn rewritten_parent_ids(
+    commit: &OriginalCommit,
+    old_to_new_oids: &HashMap<Oid, Oid>,
+) -> Vec<Oid> {
+    let mut parent_ids: Vec<Oid> = Vec::new();
+    for oid in &commit.parent_ids {
+        let parent_id = *old_to_new_oids.get(oid).unwrap_or(oid);
+        // Parents that converge on the same rewritten commit are only kept once.
+        if !parent_ids.contains(&parent_id) {
+            parent_ids.push(parent_id);
+        }
+    }
+    parent_ids
+}
+
+/// Whether two signatures name the same person at the same time.
+fn same_signature(a: &Signature, b: &Signature) -> bool {
+    a.name_bytes() == b.name_bytes() && a.email_bytes() == b.email_bytes() && a.when() == b.when()
+}
+
+/// What to do with a commit whose message is not valid utf-8, when it needs to be rewritten
+/// without its message being replaced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidUtf8Policy {
+    /// Fail the regraph with `RegraphError::CommitWithInvalidUtf8Message`.
+    Abort,
+    /// Copy the raw bytes of the message into the rewritten commit, without applying any message
+    /// substitutions.
+    PassThroughBytes,
+    /// Leave the commit as it is, so that it and any of its descendants that depend on it are
+    /// not rewritten, and keep referring to the original history.
+    Skip,
+}
+
+impl Default for InvalidUtf8Policy {
+    fn default() -> Self {
+        InvalidUtf8Policy::Abort
+    }
+}
+
+/// Rewrites applied to the content of the edited commit and of every commit rewritten on top of it,
+/// rather than just the edited one.
+struct ContentRewrites<'r> {
+    message_substitutions: &'r [(String, String)],
+    mailmap: Option<&'r Mailmap>,
+    on_invalid_utf8: InvalidUtf8Policy,
+    commit_hook: Option<&'r dyn Fn(&mut CommitBuffer)>,
+}
+
+impl ContentRewrites<'_> {
+    /// Applies each message substitution in turn to the whole of a commit's message, or `None`
+    /// if the commit is to be left as it is.
+    fn message(&self, commit: &OriginalCommit) -> Result<Option<Vec<u8>>, RegraphError> {
+        match commit.message() {
+            Some(message) => Ok(Some(
+                self.message_substitutions
+                    .iter()
+                    .fold(message.to_owned(), |message, (from, to)| message.replace(from, to))
+                    .into_bytes(),
+            )),
+            None => match self.on_invalid_utf8 {
+                InvalidUtf8Policy::Abort => Err(RegraphError::CommitWithInvalidUtf8Message {
+                    commit: commit.id,
+                    backtrace: Backtrace::capture(),
+                }),
+                InvalidUtf8Policy::PassThroughBytes => Ok(Some(commit.message.clone())),
+                InvalidUtf8Policy::Skip => Ok(None),
+            },
+        }
+    }
+
+    /// Passes a commit that is about to be written through the commit hook, if there is one.
+    fn hook(&self, buffer: &mut CommitBuffer) {
+        if let Some(commit_hook) = self.commit_hook {
+            commit_hook(buffer);
+        }
+    }
+
+    /// Maps an author or committer to their canonical identity, keeping the original time.
+    fn signature<'s>(&self, signature: Signature<'s>) -> Result<Signature<'s>, RegraphError> {
+        Ok(match self.mailmap {
+            Some(mailmap) => mailmap.resolve_signature(&signature)?,
+            None => signature,
+        })
+    }
+}
+
+/// The headers of a commit other than those every commit has, in their original order and with
+/// multi-line values joined by newlines, leaving out any signature of the commit.
+fn extra_headers(commit: &OriginalCommit) -> Vec<(String, Vec<u8>)> {
+    let mut headers: Vec<(String, Vec<u8>)> = Vec::new();
+    for line in commit.raw_header.split(|byte| *byte == b'\n') {
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(b'\n');
+                value.extend_from_slice(continuation);
+            }
+        } else if !line.is_empty() {
+            let key_end = line
+                .iter()
+                .position(|byte| *byte == b' ')
+                .unwrap_or_else(|| line.len());
+            headers.push((
+                String::from_utf8_lossy(&line[..key_end]).into_owned(),
+                line.get(key_end + 1..).unwrap_or_default().to_vec(),
+            ));
+        }
+    }
+    headers.retain(|(key, _)| {
+        !matches!(
+            key.as_str(),
+            "tree" | "parent" | "author" | "committer" | "encoding"
+        ) && !SIGNATURE_HEADERS.contains(&key.as_str())
+    });
+    headers
 }
 
 pub enum RefArg<'a> {
     AllLocalRefs,
+    /// The refs whose names match a glob pattern, such as `refs/heads/feature/*`.
+    Glob(String),
     Refs(Vec<Reference<'a>>),
 }
 
 impl<'a> RefArg<'a> {
     pub fn resolve(self, repo: &'a Repository) -> Result<Vec<Reference>, RegraphError> {
+        match self {
+            RefArg::Refs(refs) => Ok(refs),
+            ref_arg => ref_arg.find_references(repo),
+        }
+    }
+
+    /// The names of the refs this resolves to. Unlike `resolve`, this leaves the `RefArg` to be
+    /// used afterwards, e.g. to preview which refs a regraph would consider.
+    pub fn names(&self, repo: &Repository) -> Result<Vec<String>, RegraphError> {
+        Ok(match self {
+            RefArg::Refs(refs) => refs.iter().map(reference_name).collect(),
+            ref_arg => ref_arg
+                .find_references(repo)?
+                .iter()
+                .map(reference_name)
+                .collect(),
+        })
+    }
+
+    /// Looks up the refs selected by a variant other than `Refs`, which holds its refs already.
+    fn find_references<'r>(
+        &self,
+        repo: &'r Repository,
+    ) -> Result<Vec<Reference<'r>>, RegraphError> {
         Ok(match self {
             RefArg::AllLocalRefs => repo
                 .references()?
@@ -36,18 +405,58 @@ impl<'a> RefArg<'a> {
                     }
                 })
                 .collect::<Result<_, _>>()?,
-            RefArg::Refs(refs) => refs,
+            RefArg::Glob(glob) => repo.references_glob(glob)?.collect::<Result<_, _>>()?,
+            RefArg::Refs(_) => unreachable!("Refs are looked up by the caller"),
+        })
+    }
+}
+
+enum SignatureEdit<'a> {
+    Replace(&'a Signature<'a>),
+    /// Keeps the original time, and the original email if none is given.
+    Identity {
+        name: &'a str,
+        email: Option<&'a str>,
+    },
+}
+
+impl<'a> SignatureEdit<'a> {
+    fn apply(
+        &self,
+        original: &Signature,
+        commit: Oid,
+    ) -> Result<Signature<'static>, RegraphError> {
+        Ok(match self {
+            SignatureEdit::Replace(signature) => signature.to_owned(),
+            SignatureEdit::Identity { name, email } => {
+                let email = match *email {
+                    Some(email) => email,
+                    None => original.email().ok_or(
+                        RegraphError::CommitWithInvalidUtf8Signature {
+                            commit,
+                            backtrace: Backtrace::capture(),
+                        },
+                    )?,
+                };
+                Signature::new(name, email, &original.when())?
+            }
         })
     }
 }
 
+enum TreeEdit<'a> {
+    Tree(&'a Tree<'a>),
+    Oid(Oid),
+}
+
 #[derive(Default)]
 pub struct CommitEdit<'a> {
     parents: Option<&'a [&'a Commit<'a>]>,
+    added_parents: Vec<&'a Commit<'a>>,
     message: Option<&'a str>,
-    tree: Option<&'a Tree<'a>>,
-    author: Option<&'a Signature<'a>>,
-    committer: Option<&'a Signature<'a>>,
+    tree: Option<TreeEdit<'a>>,
+    author: Option<SignatureEdit<'a>>,
+    committer: Option<SignatureEdit<'a>>,
 }
 
 impl<'a> CommitEdit<'a> {
@@ -64,6 +473,14 @@ impl<'a> CommitEdit<'a> {
         self
     }
 
+    /// Append a parent after the existing parents of the commit, or after the parents given to
+    /// `edit_parents`. May be called repeatedly to append several parents. A parent that the
+    /// commit already has is only kept once, in its original place.
+    pub fn add_parent<'s>(&'s mut self, parent: &'a Commit<'a>) -> &'s mut Self {
+        self.added_parents.push(parent);
+        self
+    }
+
     pub fn edit_message<'s>(&'s mut self, message: &'a str) -> &'s mut Self {
         assert!(
             self.message.is_none(),
@@ -78,7 +495,18 @@ impl<'a> CommitEdit<'a> {
             self.tree.is_none(),
             "Overwriting previous intent to modify tree"
         );
-        self.tree = Some(tree);
+        self.tree = Some(TreeEdit::Tree(tree));
+        self
+    }
+
+    /// Like `edit_tree`, but takes the id of an existing tree object, which is looked up when the
+    /// edit is applied.
+    pub fn edit_tree_oid<'s>(&'s mut self, tree_oid: Oid) -> &'s mut Self {
+        assert!(
+            self.tree.is_none(),
+            "Overwriting previous intent to modify tree"
+        );
+        self.tree = Some(TreeEdit::Oid(tree_oid));
         self
     }
 
@@ -87,7 +515,30 @@ impl<'a> CommitEdit<'a> {
             self.author.is_none(),
             "Overwriting previous intent to modify author"
         );
-        self.author = Some(author);
+        self.author = Some(SignatureEdit::Replace(author));
+        self
+    }
+
+    /// Change the author's name and email, keeping the original author time.
+    pub fn edit_author_identity<'s>(&'s mut self, name: &'a str, email: &'a str) -> &'s mut Self {
+        assert!(
+            self.author.is_none(),
+            "Overwriting previous intent to modify author"
+        );
+        self.author = Some(SignatureEdit::Identity {
+            name,
+            email: Some(email),
+        });
+        self
+    }
+
+    /// Change only the author's name, keeping the original author email and time.
+    pub fn edit_author_name<'s>(&'s mut self, name: &'a str) -> &'s mut Self {
+        assert!(
+            self.author.is_none(),
+            "Overwriting previous intent to modify author"
+        );
+        self.author = Some(SignatureEdit::Identity { name, email: None });
         self
     }
 
@@ -96,402 +547,3342 @@ impl<'a> CommitEdit<'a> {
             self.committer.is_none(),
             "Overwriting previous intent to modify committer"
         );
-        self.committer = Some(committer);
+        self.committer = Some(SignatureEdit::Replace(committer));
+        self
+    }
+
+    /// Change the committer's name and email, keeping the original commit time.
+    pub fn edit_committer_identity<'s>(
+        &'s mut self,
+        name: &'a str,
+        email: &'a str,
+    ) -> &'s mut Self {
+        assert!(
+            self.committer.is_none(),
+            "Overwriting previous intent to modify committer"
+        );
+        self.committer = Some(SignatureEdit::Identity {
+            name,
+            email: Some(email),
+        });
+        self
+    }
+
+    /// Change only the committer's name, keeping the original committer email and time.
+    pub fn edit_committer_name<'s>(&'s mut self, name: &'a str) -> &'s mut Self {
+        assert!(
+            self.committer.is_none(),
+            "Overwriting previous intent to modify committer"
+        );
+        self.committer = Some(SignatureEdit::Identity { name, email: None });
         self
     }
 
     fn create_edited_commit(
         &self,
-        repo: &Repository,
-        original: &Commit,
+        objects: &ObjectBackend,
+        original: &OriginalCommit,
+        old_to_new_oids: &HashMap<Oid, Oid>,
+        rewrites: &ContentRewrites,
     ) -> Result<Oid, RegraphError> {
-        Ok(repo.commit(
-            None,
-            self.author.unwrap_or(&original.author()),
-            self.committer.unwrap_or(&original.committer()),
-            self.message.unwrap_or(original.message().ok_or(
-                RegraphError::CommitWithInvalidUtf8Message {
-                    commit: original.id(),
+        let author = match &self.author {
+            Some(edit) => edit.apply(&original.author, original.id)?,
+            None => rewrites.signature(original.author.clone())?,
+        };
+        let committer = match &self.committer {
+            Some(edit) => edit.apply(&original.committer, original.id)?,
+            None => rewrites.signature(original.committer.clone())?,
+        };
+        let tree_id = match &self.tree {
+            Some(TreeEdit::Tree(tree)) => tree.id(),
+            Some(TreeEdit::Oid(oid)) if objects.is_tree(*oid) => *oid,
+            Some(TreeEdit::Oid(oid)) => {
+                return Err(RegraphError::NotATree {
+                    oid: *oid,
                     backtrace: Backtrace::capture(),
-                },
-            )?),
-            self.tree.unwrap_or(&original.tree()?),
-            self.parents.unwrap_or(
-                &original
-                    .parents()
-                    .collect::<Vec<Commit>>()
-                    .iter()
-                    .collect::<Vec<&Commit>>(),
-            ),
-        )?)
+                })
+            }
+            None => original.tree_id,
+        };
+        let mut parent_ids: Vec<Oid> = match self.parents {
+            Some(parents) => parents.iter().map(|parent| parent.id()).collect(),
+            None => rewritten_parent_ids(original, old_to_new_oids),
+        };
+        for parent in &self.added_parents {
+            if !parent_ids.contains(&parent.id()) {
+                parent_ids.push(parent.id());
+            }
+        }
+        // A replacement message is always utf-8, so the original encoding only applies to the
+        // original message.
+        let (message, encoding) = match self.message {
+            Some(message) => (message.as_bytes().to_vec(), None),
+            None => match rewrites.message(original)? {
+                Some(message) => (message, original.encoding.as_deref()),
+                None => return Ok(original.id),
+            },
+        };
+        let mut buffer = CommitBuffer {
+            tree_id,
+            parent_ids,
+            author,
+            committer,
+            encoding,
+            extra_headers: extra_headers(original),
+            message: &message,
+        };
+        if buffer.matches(original) {
+            return Ok(original.id);
+        }
+        rewrites.hook(&mut buffer);
+        buffer.write(objects)
     }
 }
 
-pub trait RepositoryExt {
-    fn regraph(
-        &self,
-        refs_to_update: RefArg,
-        commit_to_edit: &Commit,
-        edit: &CommitEdit,
-    ) -> Result<(), RegraphError>;
+/// Replaces the identity of the reflog entry just written for a ref update, as git2 always
+/// records the repository's default identity. Refs that don't keep a reflog are left
+/// without one.
+fn replace_reflog_signature(
+    repo: &Repository,
+    name: &str,
+    new_oid: Oid,
+    signature: &Signature,
+    reflog_message: &str,
+) -> Result<(), git2::Error> {
+    let mut reflog = repo.reflog(name)?;
+    if reflog.get(0).map(|entry| entry.id_new()) != Some(new_oid) {
+        return Ok(());
+    }
+    reflog.remove(0, false)?;
+    reflog.append(new_oid, signature, Some(reflog_message))?;
+    reflog.write()?;
+    Ok(())
 }
 
-impl RepositoryExt for Repository {
-    fn regraph(
-        &self,
-        refs_to_update: RefArg,
-        commit_to_edit: &Commit,
-        edit: &CommitEdit,
-    ) -> Result<(), RegraphError> {
-        fn discover_old_commits(
-            repo: &Repository,
-            resolved_refs_to_update: &[Reference],
-            edited_commit_oid: Oid,
-        ) -> Result<Vec<Oid>, RegraphError> {
-            let mut revwalk = repo.revwalk()?;
-            revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
-
-            for reference in resolved_refs_to_update.iter() {
-                revwalk.push(
-                    reference
-                        .resolve()?
-                        .target()
-                        .expect("Resolved reference should have a direct target"),
-                )?;
+fn update_refs(
+    repo: &Repository,
+    resolved_refs_to_update: &[Reference],
+    old_to_new_oids: &HashMap<Oid, Oid>,
+    reflog_message: &str,
+    reflog_signature: Option<&Signature>,
+    dry_run: bool,
+) -> Result<Vec<(String, Oid, Oid)>, RegraphError> {
+    let mut updated_refs = Vec::new();
+    for reference in resolved_refs_to_update {
+        let (mut direct_ref, old_oid) = match resolve_reference(reference) {
+            Err(RegraphError::Git2Error { source, .. }) => {
+                return Err(RegraphError::RefUpdateFailed {
+                    reference: reference_name(reference),
+                    source,
+                })
             }
-            revwalk.hide(edited_commit_oid)?;
-
-            // TODO: We collect into a new vector rather than iterating them in-place, because I'm
-            // not sure if editing the git graph while iterating through the RevWalk will
-            // invalidate the iterator. This could potentially be better optimised.
-            Ok(revwalk.collect::<Result<_, _>>()?)
-        }
-
-        fn update_affected_commits(
-            repo: &Repository,
-            old_commit_oids: &[Oid],
-            old_to_new_oids: &mut HashMap<Oid, Oid>,
-        ) -> Result<(), RegraphError> {
-            for old_oid in old_commit_oids {
-                let commit = repo.find_commit(*old_oid)?;
-
-                let needs_updating = commit
-                    .parent_ids()
-                    .any(|oid| old_to_new_oids.contains_key(&oid));
-
-                if needs_updating {
-                    let parents: Vec<Commit> = commit
-                        .parent_ids()
-                        .map(|oid| *old_to_new_oids.get(&oid).unwrap_or(&oid))
-                        .map(|oid| repo.find_commit(oid.clone()))
-                        .collect::<Result<_, _>>()?;
-                    let parents_ref: Vec<&Commit> = parents.iter().collect();
-
-                    let new_oid = repo.commit(
-                        None,
-                        &commit.author(),
-                        &commit.committer(),
-                        commit
-                            .message()
-                            .ok_or(RegraphError::CommitWithInvalidUtf8Message {
-                                commit: commit.id(),
-                                backtrace: Backtrace::capture(),
-                            })?,
-                        &commit.tree()?,
-                        &parents_ref,
-                    )?;
-
-                    old_to_new_oids.insert(*old_oid, new_oid);
+            resolved => resolved?,
+        };
+        if let Some(new_oid) = old_to_new_oids.get(&old_oid) {
+            let name = reference_name(&direct_ref);
+            if !dry_run {
+                let ref_update_failed = |source| RegraphError::RefUpdateFailed {
+                    reference: name.clone(),
+                    source,
+                };
+                direct_ref
+                    .set_target(*new_oid, reflog_message)
+                    .map_err(ref_update_failed)?;
+                if let Some(signature) = reflog_signature {
+                    replace_reflog_signature(repo, &name, *new_oid, signature, reflog_message)
+                        .map_err(ref_update_failed)?;
                 }
             }
-            Ok(())
+            updated_refs.push((name, old_oid, *new_oid));
         }
+    }
+    Ok(updated_refs)
+}
 
-        fn update_refs(
-            resolved_refs_to_update: &[Reference],
-            old_edited_oid: &Oid,
-            new_edited_oid: &Oid,
-            old_to_new_oids: &HashMap<Oid, Oid>,
-        ) -> Result<(), RegraphError> {
-            let reflog_message = format!(
-                "regraph: update after editing commit {} -> {}",
-                old_edited_oid, new_edited_oid
-            );
-            for reference in resolved_refs_to_update {
-                let mut direct_ref = reference.resolve()?;
-                let old_oid = reference
-                    .target()
-                    .expect("Direct references should have a direct target");
-                if let Some(new_oid) = old_to_new_oids.get(&old_oid) {
-                    direct_ref.set_target(*new_oid, &reflog_message)?;
-                }
-            }
-            Ok(())
+/// Finds the commits that the updated refs could reach before they were moved but can no longer
+/// reach, leaving out those that were rewritten into a replacement.
+fn orphaned_commits(
+    repo: &Repository,
+    updated_refs: &[(String, Oid, Oid)],
+    old_to_new_oids: &HashMap<Oid, Oid>,
+    sorting: Sort,
+) -> Result<Vec<Oid>, RegraphError> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(sorting | Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    for (_, old_oid, new_oid) in updated_refs {
+        revwalk.push(*old_oid)?;
+        revwalk.hide(*new_oid)?;
+    }
+
+    let mut orphaned_oids = Vec::new();
+    for oid in revwalk {
+        let oid = oid?;
+        if !old_to_new_oids.contains_key(&oid) {
+            orphaned_oids.push(oid);
         }
+    }
+    Ok(orphaned_oids)
+}
+
+/// A summary of what a regraph rewrote.
+#[derive(Debug)]
+pub struct RegraphReport {
+    /// Maps each rewritten commit, including the edited commit, to its replacement.
+    pub old_to_new_oids: HashMap<Oid, Oid>,
+    /// The number of descendants of the edited commit that were rewritten into a distinct new
+    /// commit. When zero, only the edited commit itself changed.
+    pub rewritten_descendants: usize,
+    /// The commits walked to find those needing to be rewritten, in the order they were
+    /// considered, parents first.
+    pub discovered_commits: Vec<Oid>,
+    /// The names of the refs that were moved to rewritten commits.
+    pub updated_refs: Vec<String>,
+    /// Each of `updated_refs`, along with its target before and after it was moved.
+    pub ref_moves: Vec<(String, Oid, Oid)>,
+    /// The commits that were dropped from the history of the updated refs without being rewritten,
+    /// such as the parents removed by a squash. Unless something else refers to them, they will
+    /// eventually be garbage collected.
+    pub orphaned_commits: Vec<Oid>,
+    /// Situations the regraph could not fully resolve by itself, such as worktrees whose index
+    /// and working tree were left based on a commit that has since been rewritten.
+    pub warnings: Vec<String>,
+}
 
-        let mut old_to_new_oids = HashMap::new();
+/// Where a regraph gets its rewritten commits from.
+enum Rewrite<'s> {
+    /// Edit the commits, then write their rewritten descendants.
+    Edit(&'s CommitEdit<'s>),
+    /// Reuse the commits already written by a dry run.
+    Plan(&'s RegraphReport),
+}
 
-        let edited_commit_oid = edit.create_edited_commit(self, &commit_to_edit)?;
+/// Rewrites the history of a repository after editing one of its commits.
+///
+/// Options that apply to the regraph as a whole are set on this builder, which can then be `run`
+/// any number of times.
+pub struct Regraph<'a> {
+    repo: &'a Repository,
+    allow_non_fast_forward: bool,
+    rewrite_stash: bool,
+    pack_objects: bool,
+    reflog_message: Option<String>,
+    reflog_signature: Option<Signature<'static>>,
+    dry_run: bool,
+    message_substitutions: Vec<(String, String)>,
+    mailmap: Option<Mailmap>,
+    on_invalid_utf8: InvalidUtf8Policy,
+    boundaries: Vec<Oid>,
+    reset_head: Option<ResetType>,
+    commit_hook: Option<Box<dyn Fn(&mut CommitBuffer) + 'a>>,
+    sorting: Sort,
+    #[cfg(feature = "gix-backend")]
+    gix_backend: bool,
+}
 
-        if edited_commit_oid == commit_to_edit.id() {
-            return Err(RegraphError::NoChange);
+impl<'a> Regraph<'a> {
+    pub fn new(repo: &'a Repository) -> Self {
+        Regraph {
+            repo,
+            allow_non_fast_forward: false,
+            rewrite_stash: false,
+            pack_objects: false,
+            reflog_message: None,
+            reflog_signature: None,
+            dry_run: false,
+            message_substitutions: Vec::new(),
+            mailmap: None,
+            on_invalid_utf8: InvalidUtf8Policy::default(),
+            boundaries: Vec::new(),
+            reset_head: None,
+            commit_hook: None,
+            sorting: Sort::TIME,
+            #[cfg(feature = "gix-backend")]
+            gix_backend: false,
         }
+    }
 
-        old_to_new_oids.insert(commit_to_edit.id(), edited_commit_oid);
+    /// Allow refs to be moved even if some of the commits they could previously reach are dropped
+    /// from the rewritten history, e.g. when squashing commits away.
+    pub fn allow_non_fast_forward<'s>(&'s mut self, allow: bool) -> &'s mut Self {
+        self.allow_non_fast_forward = allow;
+        self
+    }
 
-        let resolved_refs_to_update = refs_to_update.resolve(self)?;
+    /// Rewrite every stash entry based on a rewritten commit, keeping the stash list intact.
+    /// Otherwise, only the latest stash entry may be rewritten, and only if `refs/stash` is
+    /// amongst the refs being updated.
+    pub fn rewrite_stash<'s>(&'s mut self, rewrite: bool) -> &'s mut Self {
+        self.rewrite_stash = rewrite;
+        self
+    }
 
-        let old_commit_oids =
-            discover_old_commits(self, &resolved_refs_to_update, edited_commit_oid)?;
+    /// Write the rewritten commits into a single pack file instead of one loose object each,
+    /// which avoids flooding the object database when rewriting long histories.
+    ///
+    /// The new objects are held in memory until every commit has been rewritten, so they are
+    /// not visible to other processes until the pack is written, and memory use grows with the
+    /// size of the rewrite. Building the pack also takes longer than writing loose objects, as
+    /// the objects are delta compressed.
+    pub fn pack_objects<'s>(&'s mut self, pack: bool) -> &'s mut Self {
+        self.pack_objects = pack;
+        self
+    }
 
-        tracing::debug!("Commits we need to update: {:#?}", old_commit_oids);
+    /// The message recorded in the reflog of each updated ref, in which `{old}` and `{new}` are
+    /// replaced with the ids of the edited commit before and after the edit.
+    pub fn reflog_message<'s>(&'s mut self, template: &str) -> &'s mut Self {
+        assert!(
+            self.reflog_message.is_none(),
+            "Overwriting previous intent to modify reflog message"
+        );
+        self.reflog_message = Some(template.to_owned());
+        self
+    }
 
-        update_affected_commits(self, &old_commit_oids, &mut old_to_new_oids)?;
+    /// The identity recorded in the reflog of each updated ref, instead of the repository's
+    /// default identity.
+    pub fn reflog_signature<'s>(&'s mut self, signature: &Signature) -> &'s mut Self {
+        assert!(
+            self.reflog_signature.is_none(),
+            "Overwriting previous intent to modify reflog signature"
+        );
+        self.reflog_signature = Some(signature.to_owned());
+        self
+    }
 
-        tracing::debug!("The following old commits have now been updated to the corresponding new commits: {:#?}", old_to_new_oids);
+    /// Work out which commits would be rewritten and which refs would be moved, without moving
+    /// any refs or worktree HEADs. The rewritten commits are still written, but are left
+    /// unreferenced.
+    pub fn dry_run<'s>(&'s mut self, dry_run: bool) -> &'s mut Self {
+        self.dry_run = dry_run;
+        self
+    }
 
-        update_refs(
-            &resolved_refs_to_update,
-            &commit_to_edit.id(),
-            &edited_commit_oid,
-            &old_to_new_oids,
-        )?;
+    /// Replace every occurrence of `from` with `to` in the message of each rewritten commit,
+    /// including the edited commit unless its message is being replaced outright. Commits that
+    /// don't descend from the edited commit are left as they are. Can be called multiple times,
+    /// with the replacements applied in order.
+    pub fn substitute_message<'s>(&'s mut self, from: &str, to: &str) -> &'s mut Self {
+        self.message_substitutions.push((from.to_owned(), to.to_owned()));
+        self
+    }
 
-        Ok(())
+    /// Map the author and committer of each rewritten commit to their canonical identity, as
+    /// `git shortlog` does, keeping their original times. This includes the edited commit,
+    /// except for any author or committer being replaced outright. Commits that don't descend
+    /// from the edited commit are left as they are.
+    ///
+    /// The repository's own mailmap can be loaded with `Repository::mailmap`.
+    pub fn mailmap<'s>(&'s mut self, mailmap: Mailmap) -> &'s mut Self {
+        assert!(
+            self.mailmap.is_none(),
+            "Overwriting previous intent to apply a mailmap"
+        );
+        self.mailmap = Some(mailmap);
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
-    use crossterm::event::{read, Event};
-    use git2::{Index, IndexAddOption, Oid, Sort, Time};
-    use std::{collections::HashMap, fs::File, io::Write};
-    use tempfile::{tempdir, TempDir};
-    use test_env_log::test;
+    /// What to do with commits whose message is not valid utf-8 and is not being replaced.
+    /// Defaults to `InvalidUtf8Policy::Abort`.
+    pub fn on_invalid_utf8<'s>(&'s mut self, policy: InvalidUtf8Policy) -> &'s mut Self {
+        self.on_invalid_utf8 = policy;
+        self
+    }
 
-    fn given_repository<'a>(
-        graph: &[(&'a str, i64, &[&str])],
-        branches: &[(&str, &str)],
-    ) -> Result<(Repository, HashMap<&'a str, Oid>, TempDir)> {
-        fn add_commit<'a, 'b>(
-            dir: &TempDir,
-            index: &'b mut Index,
-            repo: &'a Repository,
-            label: &str,
-            time_sec: i64,
-            parents: &[Commit],
-        ) -> Result<Oid> {
-            File::create(dir.path().join(label))?;
+    /// Stop the rewrite at a boundary commit: neither it nor any of its descendants is rewritten,
+    /// even if they descend from the edited commit. Commits between the edited commit and the
+    /// boundary are still rewritten. Can be called multiple times to add several boundaries.
+    ///
+    /// This splits the graph. Descendants of a boundary are never rewritten, even if they also
+    /// descend from a rewritten commit by another path, and keep referring to the original
+    /// history, including the original edited commit, along with any refs pointing to them.
+    pub fn boundary<'s>(&'s mut self, oid: Oid) -> &'s mut Self {
+        self.boundaries.push(oid);
+        self
+    }
+
+    /// Reset each worktree whose HEAD was rewritten into a commit with a different tree, as
+    /// `git commit --amend` would, instead of warning that it is still based on the old commit.
+    ///
+    /// `ResetType::Mixed` resets the index, while `ResetType::Hard` also resets the working tree,
+    /// discarding any changes to it. `ResetType::Soft` has no effect, as HEAD is already moved.
+    pub fn reset_head<'s>(&'s mut self, reset_type: ResetType) -> &'s mut Self {
+        self.reset_head = Some(reset_type);
+        self
+    }
+
+    /// Calls `hook` on the contents of each commit about to be rewritten, including the edited
+    /// commit, after its fields have been filled in but before it is written, e.g. to add custom
+    /// headers. Commits that would otherwise be left as they are are not passed to the hook.
+    pub fn commit_hook<'s>(&'s mut self, hook: impl Fn(&mut CommitBuffer) + 'a) -> &'s mut Self {
+        assert!(
+            self.commit_hook.is_none(),
+            "Overwriting previous intent to set a commit hook"
+        );
+        self.commit_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// How to order commits that topological order alone leaves tied when walking the commits to
+    /// rewrite, which decides the order in which they are rewritten. The walk is always
+    /// topological and parents first, so `Sort::TOPOLOGICAL` and `Sort::REVERSE` are implied.
+    ///
+    /// Defaults to `Sort::TIME`, which orders tied commits by commit time, oldest first. This is
+    /// the same on every run over the same commits.
+    pub fn sorting<'s>(&'s mut self, sort: Sort) -> &'s mut Self {
+        self.sorting = sort;
+        self
+    }
+
+    /// Read the commits to rewrite and write their replacements with the pure-Rust gix crate
+    /// instead of libgit2, which still finds the commits to rewrite and moves the refs. The
+    /// rewritten commits are identical either way. Ignored when packing objects, which relies on
+    /// an in-memory libgit2 backend.
+    #[cfg(feature = "gix-backend")]
+    pub fn gix_backend<'s>(&'s mut self, enable: bool) -> &'s mut Self {
+        self.gix_backend = enable;
+        self
+    }
+
+    pub fn run(
+        &self,
+        refs_to_update: RefArg,
+        commit_to_edit: &Commit,
+        edit: &CommitEdit,
+    ) -> Result<RegraphReport, RegraphError> {
+        self.rewrite(
+            refs_to_update,
+            std::slice::from_ref(commit_to_edit),
+            Rewrite::Edit(edit),
+        )
+    }
+
+    /// Moves refs as planned by an earlier dry run of `run` with the same commit to edit, reusing
+    /// the commits that the dry run already wrote instead of writing them again.
+    pub fn apply(
+        &self,
+        refs_to_update: RefArg,
+        commit_to_edit: &Commit,
+        plan: &RegraphReport,
+    ) -> Result<RegraphReport, RegraphError> {
+        self.rewrite(
+            refs_to_update,
+            std::slice::from_ref(commit_to_edit),
+            Rewrite::Plan(plan),
+        )
+    }
+
+    /// Applies the same edit to every commit in the range `base..tip`, i.e. those reachable from
+    /// `tip` but not from `base`, before rewriting their descendants. Commits reachable from
+    /// `base` are left as they are, and the edited commits keep their parents, rewritten where
+    /// they are part of the range. This suits uniform edits such as normalising identities, so
+    /// the edit may not change parents.
+    pub fn run_range(
+        &self,
+        refs_to_update: RefArg,
+        base: &Commit,
+        tip: &Commit,
+        edit: &CommitEdit,
+    ) -> Result<RegraphReport, RegraphError> {
+        assert!(
+            edit.parents.is_none() && edit.added_parents.is_empty(),
+            "Editing the parents of a range of commits is not supported"
+        );
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.set_sorting(self.sorting | Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        revwalk.push(tip.id())?;
+        revwalk.hide(base.id())?;
+        let mut commits_to_edit = Vec::new();
+        for oid in revwalk {
+            commits_to_edit.push(self.repo.find_commit(oid?)?);
+        }
+        self.rewrite(refs_to_update, &commits_to_edit, Rewrite::Edit(edit))
+    }
+
+    /// Edits each of `commits_to_edit`, which must be in topological order, then rewrites their
+    /// descendants, unless a plan already holds the rewritten commits. The last of them stands
+    /// for the whole edit in errors and reflog messages.
+    fn rewrite(
+        &self,
+        refs_to_update: RefArg,
+        commits_to_edit: &[Commit],
+        rewrite: Rewrite,
+    ) -> Result<RegraphReport, RegraphError> {
+        /// The repositories of the current worktree and of every linked worktree, each of which
+        /// has its own HEAD.
+        fn worktree_repos(repo: &Repository) -> Result<Vec<Repository>, RegraphError> {
+            // The main worktree belongs to the common dir, which is only the caller's own
+            // repository when it wasn't opened from a linked worktree.
+            let mut worktree_repos = vec![Repository::open(repo.commondir())?];
+            for name in repo.worktrees()?.iter().flatten() {
+                let worktree = repo.find_worktree(name)?;
+                if worktree.validate().is_err() {
+                    // The worktree has been deleted without being pruned.
+                    continue;
+                }
+                worktree_repos.push(Repository::open_from_worktree(&worktree)?);
+            }
+            // Reopening loses any working directory set on the caller's handle, e.g. through
+            // core.worktree or `Repository::set_workdir`.
+            if let Some(workdir) = repo.workdir() {
+                for worktree_repo in &worktree_repos {
+                    if worktree_repo.path() == repo.path() {
+                        worktree_repo.set_workdir(workdir, false)?;
+                    }
+                }
+            }
+            Ok(worktree_repos)
+        }
+
+        fn worktree_display_name(worktree_repo: &Repository) -> String {
+            worktree_repo
+                .workdir()
+                .unwrap_or_else(|| worktree_repo.path())
+                .display()
+                .to_string()
+        }
+
+        fn detached_head(worktree_repo: &Repository) -> Result<Option<Oid>, RegraphError> {
+            Ok(if worktree_repo.head_detached()? {
+                worktree_repo.head()?.target()
+            } else {
+                None
+            })
+        }
+
+        fn is_reachable_from_any(
+            repo: &Repository,
+            tips: &[Oid],
+            commit_oid: Oid,
+        ) -> Result<bool, RegraphError> {
+            for tip in tips {
+                // Refs may point to tags, or even to trees and blobs which can't reach any commit.
+                let tip_commit_oid = match repo.find_object(*tip, None)?.peel_to_commit() {
+                    Ok(tip_commit) => tip_commit.id(),
+                    Err(_) => continue,
+                };
+                if tip_commit_oid == commit_oid
+                    || repo.graph_descendant_of(tip_commit_oid, commit_oid)?
+                {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+
+        fn discover_old_commits(
+            repo: &Repository,
+            tips: &[Oid],
+            first_oids_to_edit: &[Oid],
+            edited_commit_oid: Oid,
+            boundaries: &[Oid],
+            sorting: Sort,
+        ) -> Result<Vec<Oid>, RegraphError> {
+            let mut revwalk = repo.revwalk()?;
+            revwalk.set_sorting(sorting | Sort::TOPOLOGICAL | Sort::REVERSE)?;
+
+            for tip in tips {
+                // Tips that can't reach any edited commit have nothing to rewrite, so their
+                // history isn't walked at all. Any tip reaching an edited commit also reaches one
+                // of the first commits to edit, so only those need checking.
+                let mut contains_edit = false;
+                for old_oid in first_oids_to_edit {
+                    if is_reachable_from_any(repo, std::slice::from_ref(tip), *old_oid)? {
+                        contains_edit = true;
+                        break;
+                    }
+                }
+                if contains_edit {
+                    revwalk.push(*tip)?;
+                }
+            }
+            revwalk.hide(edited_commit_oid)?;
+
+            // TODO: We collect into a new vector rather than iterating them in-place, because I'm
+            // not sure if editing the git graph while iterating through the RevWalk will
+            // invalidate the iterator. This could potentially be better optimised.
+            let mut old_commit_oids = Vec::new();
+            // Hiding a boundary would also hide its ancestors, which may still need rewriting,
+            // so only the boundary and its descendants are left out. Parents are walked before
+            // their children, so whether a commit is past a boundary follows from its parents,
+            // except for parents that weren't walked.
+            let mut walked_oids = HashSet::new();
+            let mut past_boundary_oids = HashSet::new();
+            for oid in revwalk {
+                let oid = oid?;
+                walked_oids.insert(oid);
+                if !boundaries.is_empty() {
+                    let parent_ids: Vec<Oid> = repo.find_commit(oid)?.parent_ids().collect();
+                    let mut past_boundary = boundaries.contains(&oid)
+                        || parent_ids
+                            .iter()
+                            .any(|parent_id| past_boundary_oids.contains(parent_id));
+                    if !past_boundary
+                        && parent_ids
+                            .iter()
+                            .any(|parent_id| !walked_oids.contains(parent_id))
+                    {
+                        for boundary in boundaries {
+                            if repo.graph_descendant_of(oid, *boundary)? {
+                                past_boundary = true;
+                                break;
+                            }
+                        }
+                    }
+                    if past_boundary {
+                        past_boundary_oids.insert(oid);
+                        continue;
+                    }
+                }
+                old_commit_oids.push(oid);
+            }
+            Ok(old_commit_oids)
+        }
+
+        /// Rewrites each commit whose parents were rewritten, in the given order, which must
+        /// visit parents first. Only the commit itself is looked up: rewritten parents are
+        /// referred to by the ids recorded in `old_to_new_oids` as they are written, so they are
+        /// never read back from the object database.
+        fn update_affected_commits(
+            objects: &ObjectBackend,
+            old_commit_oids: &[Oid],
+            old_to_new_oids: &mut HashMap<Oid, Oid>,
+            rewrites: &ContentRewrites,
+        ) -> Result<usize, RegraphError> {
+            let mut rewritten_count = 0;
+            for old_oid in old_commit_oids {
+                if old_to_new_oids.contains_key(old_oid) {
+                    // Already rewritten as one of the edited commits.
+                    continue;
+                }
+                let commit = objects.find_commit(*old_oid)?;
+
+                let parents_changed = commit
+                    .parent_ids
+                    .iter()
+                    .any(|oid| old_to_new_oids.contains_key(oid));
+                if !parents_changed {
+                    // Not a descendant of the edit, so left as it is.
+                    continue;
+                }
+
+                let message = match rewrites.message(&commit)? {
+                    Some(message) => message,
+                    None => continue,
+                };
+                let mut buffer = CommitBuffer {
+                    tree_id: commit.tree_id,
+                    parent_ids: rewritten_parent_ids(&commit, old_to_new_oids),
+                    author: rewrites.signature(commit.author.clone())?,
+                    committer: rewrites.signature(commit.committer.clone())?,
+                    encoding: commit.encoding.as_deref(),
+                    extra_headers: extra_headers(&commit),
+                    message: &message,
+                };
+                rewrites.hook(&mut buffer);
+
+                let new_oid = buffer.write(objects)?;
+
+                if new_oid != *old_oid {
+                    old_to_new_oids.insert(*old_oid, new_oid);
+                    rewritten_count += 1;
+                }
+            }
+            Ok(rewritten_count)
+        }
+
+        fn check_fast_forwards(
+            repo: &Repository,
+            resolved_refs_to_update: &[Reference],
+            old_to_new_oids: &HashMap<Oid, Oid>,
+        ) -> Result<(), RegraphError> {
+            for reference in resolved_refs_to_update {
+                let (_, old_oid) = resolve_reference(reference)?;
+                if let Some(new_oid) = old_to_new_oids.get(&old_oid) {
+                    // Everything the ref could previously reach must either still be reachable,
+                    // or have been replaced by its rewritten counterpart.
+                    let mut revwalk = repo.revwalk()?;
+                    revwalk.push(old_oid)?;
+                    revwalk.hide(*new_oid)?;
+                    for oid in revwalk {
+                        if !old_to_new_oids.contains_key(&oid?) {
+                            return Err(RegraphError::NonFastForward {
+                                reference: reference_name(reference),
+                            });
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Every stash entry, newest first, as the stashed commit along with the committer and
+        /// message of its reflog entry.
+        fn stash_entries(
+            repo: &Repository,
+        ) -> Result<Vec<(Oid, Signature<'static>, Option<String>)>, RegraphError> {
+            Ok(repo
+                .reflog(STASH_REF)?
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.id_new(),
+                        entry.committer().to_owned(),
+                        entry.message().map(String::from),
+                    )
+                })
+                .collect())
+        }
+
+        /// Points each stash entry at its rewritten commit, returning the old and new target of
+        /// `refs/stash` if it moved.
+        fn rewrite_stash(
+            repo: &Repository,
+            stash_entries: &[(Oid, Signature, Option<String>)],
+            old_to_new_oids: &HashMap<Oid, Oid>,
+            reflog_message: &str,
+            dry_run: bool,
+        ) -> Result<Option<(Oid, Oid)>, RegraphError> {
+            if !stash_entries
+                .iter()
+                .any(|(oid, _, _)| old_to_new_oids.contains_key(oid))
+            {
+                return Ok(None);
+            }
+
+            let old_latest = stash_entries[0].0;
+            let new_latest = *old_to_new_oids.get(&old_latest).unwrap_or(&old_latest);
+            let update = Some((old_latest, new_latest)).filter(|(old, new)| old != new);
+            if dry_run {
+                return Ok(update);
+            }
+
+            repo.reference(STASH_REF, new_latest, true, reflog_message)?;
+
+            // The stash list is the reflog of refs/stash, so it is rebuilt from scratch to replace
+            // each entry rather than appending our own.
+            let mut reflog = repo.reflog(STASH_REF)?;
+            while !reflog.is_empty() {
+                reflog.remove(0, false)?;
+            }
+            for (oid, committer, message) in stash_entries.iter().rev() {
+                reflog.append(
+                    *old_to_new_oids.get(oid).unwrap_or(oid),
+                    committer,
+                    message.as_deref(),
+                )?;
+            }
+            reflog.write()?;
+
+            Ok(update)
+        }
+
+        /// Moves detached worktree HEADs along with the rewrite when `move_detached_heads` is
+        /// set, and warns about any worktree whose checked out commit was rewritten to one with a
+        /// different tree, since its index and working tree no longer match HEAD.
+        fn update_worktrees(
+            repo: &Repository,
+            worktree_repos: &[Repository],
+            move_detached_heads: bool,
+            reset_head: Option<ResetType>,
+            old_to_new_oids: &HashMap<Oid, Oid>,
+            updated_refs: &[(String, Oid, Oid)],
+        ) -> Result<Vec<String>, RegraphError> {
+            let mut warnings = Vec::new();
+            for worktree_repo in worktree_repos {
+                let worktree_name = worktree_display_name(worktree_repo);
+                let (old_oid, new_oid) = if let Some(old_oid) = detached_head(worktree_repo)? {
+                    let new_oid = match old_to_new_oids.get(&old_oid) {
+                        Some(new_oid) => *new_oid,
+                        None => continue,
+                    };
+                    if !move_detached_heads {
+                        warnings.push(format!(
+                            "Worktree {} has a detached HEAD at {}, which was rewritten to {}, \
+                             but HEAD was left untouched.",
+                            worktree_name, old_oid, new_oid
+                        ));
+                        continue;
+                    }
+                    worktree_repo.set_head_detached(new_oid)?;
+                    (old_oid, new_oid)
+                } else {
+                    let head = worktree_repo.find_reference("HEAD")?;
+                    let branch = match head.symbolic_target() {
+                        Some(branch) => branch,
+                        None => continue,
+                    };
+                    match updated_refs.iter().find(|(name, _, _)| name == branch) {
+                        Some((_, old_oid, new_oid)) => (*old_oid, *new_oid),
+                        None => continue,
+                    }
+                };
+                if repo.find_commit(old_oid)?.tree_id() == repo.find_commit(new_oid)?.tree_id() {
+                    continue;
+                }
+                match reset_head {
+                    Some(reset_type @ ResetType::Mixed) | Some(reset_type @ ResetType::Hard)
+                        if !worktree_repo.is_bare() =>
+                    {
+                        let new_commit = worktree_repo.find_object(new_oid, None)?;
+                        worktree_repo.reset(&new_commit, reset_type, None)?;
+                    }
+                    _ => warnings.push(format!(
+                        "The index and working tree of worktree {} are still based on {}, which \
+                         was rewritten to {} with a different tree.",
+                        worktree_name, old_oid, new_oid
+                    )),
+                }
+            }
+            for warning in &warnings {
+                tracing::warn!("{}", warning);
+            }
+            Ok(warnings)
+        }
+
+        let repo = self.repo;
+
+        let commit_to_edit = commits_to_edit.last().ok_or(RegraphError::NoChange)?;
+
+        // Only a regraph of all local refs follows detached worktree HEADs, as they are not
+        // refs that could be named explicitly.
+        let move_detached_heads = matches!(refs_to_update, RefArg::AllLocalRefs);
+        let mut resolved_refs_to_update = refs_to_update.resolve(repo)?;
+
+        let stash_entries = if self.rewrite_stash {
+            // The stash is rewritten separately, as moving refs/stash directly would add a
+            // stash entry rather than replace one.
+            resolved_refs_to_update.retain(|reference| reference.name() != Some(STASH_REF));
+            stash_entries(repo)?
+        } else {
+            Vec::new()
+        };
+
+        let worktree_repos = worktree_repos(repo)?;
+
+        let mut tips = Vec::new();
+        for reference in resolved_refs_to_update.iter() {
+            tips.push(resolve_reference(reference)?.1);
+        }
+        if move_detached_heads {
+            for worktree_repo in worktree_repos.iter() {
+                tips.extend(detached_head(worktree_repo)?);
+            }
+        }
+        tips.extend(stash_entries.iter().map(|(oid, _, _)| *oid));
+
+        if !is_reachable_from_any(repo, &tips, commit_to_edit.id())? {
+            return Err(RegraphError::CommitNotReachable {
+                commit: commit_to_edit.id(),
+            });
+        }
+
+        let (old_to_new_oids, old_commit_oids, rewritten_descendants) = match rewrite {
+            Rewrite::Plan(plan) => (
+                plan.old_to_new_oids.clone(),
+                plan.discovered_commits.clone(),
+                plan.rewritten_descendants,
+            ),
+            Rewrite::Edit(edit) => {
+                // When packing, new objects are written through a separate handle so that the
+                // in-memory backend collecting them doesn't outlive the regraph on the caller's
+                // handle.
+                let pack_repo;
+                let object_repo = if self.pack_objects {
+                    pack_repo = Repository::open(repo.path())?;
+                    &pack_repo
+                } else {
+                    repo
+                };
+                let odb = object_repo.odb()?;
+                let mempack = if self.pack_objects {
+                    Some(odb.add_new_mempack_backend(999)?)
+                } else {
+                    None
+                };
+
+                let rewrites = ContentRewrites {
+                    message_substitutions: &self.message_substitutions,
+                    mailmap: self.mailmap.as_ref(),
+                    on_invalid_utf8: self.on_invalid_utf8,
+                    commit_hook: self.commit_hook.as_deref(),
+                };
+
+                #[cfg(feature = "gix-backend")]
+                let objects = if self.gix_backend && !self.pack_objects {
+                    ObjectBackend::Gix(gix_backend::Objects::open(repo.path())?)
+                } else {
+                    ObjectBackend::Git2(object_repo)
+                };
+                #[cfg(not(feature = "gix-backend"))]
+                let objects = ObjectBackend::Git2(object_repo);
+
+                let mut old_to_new_oids = HashMap::new();
+
+                for original in commits_to_edit {
+                    let original = objects.find_commit(original.id())?;
+                    let edited_oid = edit.create_edited_commit(
+                        &objects,
+                        &original,
+                        &old_to_new_oids,
+                        &rewrites,
+                    )?;
+                    if edited_oid != original.id {
+                        old_to_new_oids.insert(original.id, edited_oid);
+                    }
+                }
+
+                let edited_commit_oid = match old_to_new_oids.get(&commit_to_edit.id()) {
+                    Some(edited_commit_oid) => *edited_commit_oid,
+                    None => return Err(RegraphError::NoChange),
+                };
+
+                // The commits to edit are either a single commit or a range, so every one of them
+                // descends from one whose parents are all outside of them, e.g. the children of
+                // the base of a range.
+                let oids_to_edit: HashSet<Oid> = commits_to_edit.iter().map(Commit::id).collect();
+                let first_oids_to_edit: Vec<Oid> = commits_to_edit
+                    .iter()
+                    .filter(|commit| {
+                        !commit
+                            .parent_ids()
+                            .any(|parent| oids_to_edit.contains(&parent))
+                    })
+                    .map(Commit::id)
+                    .collect();
+                let old_commit_oids = discover_old_commits(
+                    repo,
+                    &tips,
+                    &first_oids_to_edit,
+                    edited_commit_oid,
+                    &self.boundaries,
+                    self.sorting,
+                )?;
+
+                tracing::debug!("Commits we need to update: {:#?}", old_commit_oids);
+
+                let rewritten_descendants = update_affected_commits(
+                    &objects,
+                    &old_commit_oids,
+                    &mut old_to_new_oids,
+                    &rewrites,
+                )?;
+
+                if let Some(mempack) = &mempack {
+                    let mut pack = Buf::new();
+                    mempack.dump(object_repo, &mut pack)?;
+                    let mut packwriter = odb.packwriter()?;
+                    packwriter.write_all(&pack)?;
+                    packwriter.commit()?;
+                    mempack.reset()?;
+                }
+                (old_to_new_oids, old_commit_oids, rewritten_descendants)
+            }
+        };
+
+        let edited_commit_oid = match old_to_new_oids.get(&commit_to_edit.id()) {
+            Some(edited_commit_oid) => *edited_commit_oid,
+            None => return Err(RegraphError::NoChange),
+        };
+
+        tracing::debug!("The following old commits have now been updated to the corresponding new commits: {:#?}", old_to_new_oids);
+
+        if !self.allow_non_fast_forward {
+            check_fast_forwards(repo, &resolved_refs_to_update, &old_to_new_oids)?;
+        }
+
+        let reflog_message = self
+            .reflog_message
+            .as_deref()
+            .unwrap_or(DEFAULT_REFLOG_MESSAGE)
+            .replace("{old}", &commit_to_edit.id().to_string())
+            .replace("{new}", &edited_commit_oid.to_string());
+        let mut updated_refs = update_refs(
+            repo,
+            &resolved_refs_to_update,
+            &old_to_new_oids,
+            &reflog_message,
+            self.reflog_signature.as_ref(),
+            self.dry_run,
+        )?;
+
+        if let Some((old_oid, new_oid)) = rewrite_stash(
+            repo,
+            &stash_entries,
+            &old_to_new_oids,
+            &reflog_message,
+            self.dry_run,
+        )? {
+            updated_refs.push((STASH_REF.to_owned(), old_oid, new_oid));
+        }
+
+        let orphaned_commits =
+            orphaned_commits(repo, &updated_refs, &old_to_new_oids, self.sorting)?;
+
+        let warnings = if self.dry_run {
+            Vec::new()
+        } else {
+            update_worktrees(
+                repo,
+                &worktree_repos,
+                move_detached_heads,
+                self.reset_head,
+                &old_to_new_oids,
+                &updated_refs,
+            )?
+        };
+
+        Ok(RegraphReport {
+            old_to_new_oids,
+            rewritten_descendants,
+            discovered_commits: old_commit_oids,
+            updated_refs: updated_refs.iter().map(|(name, _, _)| name.clone()).collect(),
+            ref_moves: updated_refs,
+            orphaned_commits,
+            warnings,
+        })
+    }
+}
+
+pub trait RepositoryExt {
+    fn regraph(
+        &self,
+        refs_to_update: RefArg,
+        commit_to_edit: &Commit,
+        edit: &CommitEdit,
+    ) -> Result<RegraphReport, RegraphError>;
+
+    /// Checks that none of the refs updated by a regraph can still reach a commit that was
+    /// rewritten, which would mean the old and new histories have been mixed.
+    fn regraph_verify(&self, report: &RegraphReport) -> Result<(), RegraphError>;
+
+    /// Moves refs that point to rewritten commits back to the commits they were rewritten from,
+    /// given the `old_to_new_oids` of a regraph. Fails without moving any refs if any of the
+    /// original commits have since been garbage collected.
+    ///
+    /// A rewritten commit may be one that already existed, such as when an edit makes a commit
+    /// identical to its sibling, and a ref pointing to it may never have been moved. Such refs
+    /// can't be told apart from those the regraph moved, so this fails without moving any refs
+    /// if one of them points to a rewritten commit that can be reached from an original commit.
+    /// Prefer `regraph_undo_report`, which knows which refs were moved.
+    fn regraph_undo(
+        &self,
+        refs_to_update: RefArg,
+        old_to_new_oids: &HashMap<Oid, Oid>,
+    ) -> Result<(), RegraphError>;
+
+    /// Moves each ref that a regraph moved back to its original target, leaving any ref that
+    /// has moved again since. Fails without moving any refs if any of the original targets
+    /// have since been garbage collected.
+    fn regraph_undo_report(&self, report: &RegraphReport) -> Result<(), RegraphError>;
+}
+
+impl RepositoryExt for Repository {
+    fn regraph(
+        &self,
+        refs_to_update: RefArg,
+        commit_to_edit: &Commit,
+        edit: &CommitEdit,
+    ) -> Result<RegraphReport, RegraphError> {
+        Regraph::new(self).run(refs_to_update, commit_to_edit, edit)
+    }
+
+    fn regraph_verify(&self, report: &RegraphReport) -> Result<(), RegraphError> {
+        for name in &report.updated_refs {
+            let mut revwalk = self.revwalk()?;
+            revwalk.push_ref(name)?;
+            for oid in revwalk {
+                let oid = oid?;
+                if report.old_to_new_oids.contains_key(&oid) {
+                    return Err(RegraphError::StaleCommit {
+                        reference: name.clone(),
+                        commit: oid,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn regraph_undo(
+        &self,
+        refs_to_update: RefArg,
+        old_to_new_oids: &HashMap<Oid, Oid>,
+    ) -> Result<(), RegraphError> {
+        let odb = self.odb()?;
+        let mut new_to_old_oids = HashMap::new();
+        let mut collapsed_oids = Vec::new();
+        for (old_oid, new_oid) in old_to_new_oids {
+            if !odb.exists(*old_oid) {
+                return Err(RegraphError::MissingObject { oid: *old_oid });
+            }
+            if new_to_old_oids.insert(*new_oid, *old_oid).is_some() {
+                collapsed_oids.push(*new_oid);
+            }
+        }
+
+        // Every commit that can be reached from an original commit existed before the regraph.
+        let mut revwalk = self.revwalk()?;
+        for old_oid in old_to_new_oids.keys() {
+            revwalk.push(*old_oid)?;
+        }
+        let mut preexisting_oids = HashSet::new();
+        for oid in revwalk {
+            let oid = oid?;
+            if new_to_old_oids.contains_key(&oid) {
+                preexisting_oids.insert(oid);
+            }
+        }
+
+        let resolved_refs_to_update = refs_to_update.resolve(self)?;
+        for reference in &resolved_refs_to_update {
+            let (_, target) = resolve_reference(reference)?;
+            if collapsed_oids.contains(&target) {
+                return Err(RegraphError::AmbiguousUndo {
+                    reference: reference_name(reference),
+                    commit: target,
+                });
+            }
+            if preexisting_oids.contains(&target) {
+                return Err(RegraphError::PreexistingUndoTarget {
+                    reference: reference_name(reference),
+                    commit: target,
+                });
+            }
+        }
+
+        update_refs(
+            self,
+            &resolved_refs_to_update,
+            &new_to_old_oids,
+            "regraph: undo",
+            None,
+            false,
+        )?;
+        Ok(())
+    }
+
+    fn regraph_undo_report(&self, report: &RegraphReport) -> Result<(), RegraphError> {
+        let odb = self.odb()?;
+        for (_, old_oid, _) in &report.ref_moves {
+            if !odb.exists(*old_oid) {
+                return Err(RegraphError::MissingObject { oid: *old_oid });
+            }
+        }
+
+        for (name, old_oid, new_oid) in &report.ref_moves {
+            let ref_update_failed = |source| RegraphError::RefUpdateFailed {
+                reference: name.clone(),
+                source,
+            };
+            let mut reference = match self.find_reference(name) {
+                Ok(reference) => reference,
+                Err(error) if error.code() == git2::ErrorCode::NotFound => continue,
+                Err(error) => return Err(ref_update_failed(error)),
+            };
+            if reference.target() == Some(*new_oid) {
+                reference
+                    .set_target(*old_oid, "regraph: undo")
+                    .map_err(ref_update_failed)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use crossterm::event::{read, Event};
+    use git2::{
+        build::CheckoutBuilder, BranchType, Index, IndexAddOption, Oid, ResetType, Sort, Time,
+        WorktreeAddOptions,
+    };
+    use proptest::{prelude::*, sample};
+    use std::{collections::HashMap, fs::File, io::Write};
+    use tempfile::{tempdir, TempDir};
+    use test_env_log::test;
+
+    fn given_repository<'a>(
+        graph: &[(&'a str, i64, &[&str])],
+        branches: &[(&str, &str)],
+    ) -> Result<(Repository, HashMap<&'a str, Oid>, TempDir)> {
+        fn add_commit<'a, 'b>(
+            dir: &TempDir,
+            index: &'b mut Index,
+            repo: &'a Repository,
+            label: &str,
+            time_sec: i64,
+            parents: &[Commit],
+        ) -> Result<Oid> {
+            File::create(dir.path().join(label))?;
+            let mut shared = File::create(dir.path().join("shared"))?;
+            writeln!(shared, "{}", label)?;
+            index.add_all(&["."], IndexAddOption::DEFAULT, None)?;
+            let email = format!("{}-email", label);
+            let time = Time::new(time_sec, 0);
+            let author = Signature::new(&format!("{}-author", label), &email, &time)?;
+            let committer = Signature::new(&format!("{}-comitter", label), &email, &time)?;
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let parents_refs: Vec<&Commit> = parents.iter().collect();
+            Ok(repo.commit(None, &author, &committer, label, &tree, &parents_refs)?)
+        }
+        let dir = tempdir()?;
+        let repo = Repository::init(&dir)?;
+        let mut index = repo.index()?;
+        let mut label_to_commit_oid = HashMap::new();
+        for (label, time, parents) in graph {
+            assert!(
+                !label_to_commit_oid.contains_key(label),
+                "No duplicate commit labels"
+            );
+
+            let mut parent_commits = Vec::new();
+            for parent in *parents {
+                parent_commits.push(repo.find_commit(*label_to_commit_oid.get(parent).unwrap())?);
+            }
+
+            let commit_oid = add_commit(&dir, &mut index, &repo, label, *time, &parent_commits)?;
+
+            label_to_commit_oid.insert(*label, commit_oid);
+        }
+        for (branch_name, target) in branches {
+            let commit = repo.find_commit(*label_to_commit_oid.get(target).unwrap())?;
+            repo.branch(branch_name, &commit, true)?;
+        }
+        Ok((repo, label_to_commit_oid, dir))
+    }
+
+    fn label_to_commit_reachable_from_ref<'a>(
+        repo: &'a Repository,
+        reference: &str,
+    ) -> Result<HashMap<String, Commit<'a>>> {
+        let mut label_to_commit = HashMap::new();
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push_ref(reference)?;
+
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+
+        for found_commit in revwalk {
+            let commit_oid = found_commit?;
+            let commit = repo.find_commit(commit_oid)?;
+            let label = commit.message().unwrap().to_string();
+            label_to_commit.insert(label, commit.clone());
+        }
+
+        Ok(label_to_commit)
+    }
+
+    fn pause(message: &str) -> Result<()> {
+        if std::env::var("PAUSE").is_ok() {
+            println!("{} [Press any key to continue]", message);
+            while !matches!(read()?, Event::Key(_)) {}
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_squash_to_root() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),         // With main root.
+                ("B", 1, &[]),         // With subtree root.
+                ("C", 2, &["B"]),      // With more than one commit in subtree.
+                ("D", 3, &["A", "C"]), // With subtree merged into main.
+                ("E", 4, &["D"]),      // With commit after merge.
+            ],
+            &[("master", "E")],
+        )?;
+        pause("Created repo")?;
+
+        // WHEN we squash B-C by removing parents of C.
+        Regraph::new(&repo).allow_non_fast_forward(true).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_parents(&[]),
+        )?;
+        pause("Regraph complete")?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN
+        assert_eq!(
+            commits.get("A").unwrap().id(),
+            *label_to_commit_oid.get("A").unwrap(),
+            "Commit 'A' should remain unaffected, since it doesn't depend on 'C'"
+        );
+
+        // THEN
+        assert!(
+            !commits.contains_key("B"),
+            "Commit 'B' should no longer be in the graph."
+        );
+
+        // THEN
+        assert_eq!(
+            commits.get("C").unwrap().parent_count(),
+            0,
+            "Commit 'C' should have no parent."
+        );
+
+        // THEN
+        assert_eq!(
+            commits.get("D").unwrap().parent_count(),
+            2,
+            "Commit 'D' should still have 2 parents."
+        );
+
+        // THEN
+        assert_eq!(
+            commits.get("D").unwrap().parent_id(0)?,
+            commits.get("A").unwrap().id(),
+            "Commit 'D' should still have 'A' as its first parent."
+        );
+
+        // THEN
+        assert_eq!(
+            commits.get("D").unwrap().parent_id(1)?,
+            commits.get("C").unwrap().id(),
+            "Commit 'D' should still have 'C' as its second parent."
+        );
+
+        // THEN
+        assert!(commits.contains_key("E"), "Commit 'E' is updated");
+
+        // THEN all commits should still have the same trees and signatures.
+        for (label, new_commit) in commits.iter() {
+            let old_commit = repo.find_commit(*label_to_commit_oid.get(label as &str).unwrap())?;
+            assert_eq!(
+                new_commit.tree_id(),
+                old_commit.tree_id(),
+                "{}'s tree should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.author().email().unwrap(),
+                old_commit.author().email().unwrap(),
+                "{}'s author email should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.author().name().unwrap(),
+                old_commit.author().name().unwrap(),
+                "{}'s author name should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.author().when().seconds(),
+                old_commit.author().when().seconds(),
+                "{}'s author date should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.author().when().offset_minutes(),
+                old_commit.author().when().offset_minutes(),
+                "{}'s author date should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.author().when().sign(),
+                old_commit.author().when().sign(),
+                "{}'s author date should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.committer().email().unwrap(),
+                old_commit.committer().email().unwrap(),
+                "{}'s committer email should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.committer().name().unwrap(),
+                old_commit.committer().name().unwrap(),
+                "{}'s committer name should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.committer().when().seconds(),
+                old_commit.committer().when().seconds(),
+                "{}'s committer date should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.committer().when().offset_minutes(),
+                old_commit.committer().when().offset_minutes(),
+                "{}'s committer date should be untouched",
+                label
+            );
+            assert_eq!(
+                new_commit.committer().when().sign(),
+                old_commit.committer().when().sign(),
+                "{}'s committer date should be untouched",
+                label
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_non_fast_forward_by_default() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit that will be dropped.
+                ("C", 2, &["B"]), // With a tip commit.
+            ],
+            &[("master", "C")],
+        )?;
+
+        // WHEN we drop A-B by removing the parents of C, without allowing non-fast-forwards.
+        let result = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_parents(&[]),
+        );
+
+        // THEN the regraph is rejected, naming the ref that would lose commits.
+        assert!(
+            matches!(
+                result,
+                Err(RegraphError::NonFastForward { ref reference }) if reference == "refs/heads/master"
+            ),
+            "Moving master should be rejected as a non-fast-forward"
+        );
+
+        // THEN the ref is left untouched.
+        assert_eq!(
+            repo.find_reference("refs/heads/master")?.target().unwrap(),
+            *label_to_commit_oid.get("C").unwrap(),
+            "master should still point to the original 'C'"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_allows_non_fast_forward_when_forced() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit that will be dropped.
+                ("C", 2, &["B"]), // With a tip commit.
+            ],
+            &[("master", "C")],
+        )?;
+
+        // WHEN we drop A-B by removing the parents of C, allowing non-fast-forwards.
+        Regraph::new(&repo).allow_non_fast_forward(true).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_parents(&[]),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN master is moved to the rewritten 'C', which is now the only commit.
+        assert_eq!(commits.len(), 1, "Only 'C' should remain in the graph");
+        assert_eq!(
+            commits.get("C").unwrap().parent_count(),
+            0,
+            "Commit 'C' should have no parent."
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_change_committer_identity_keeping_date() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With a commit to be updated.
+            ],
+            &[("master", "C")],
+        )?;
+        let original_b = repo.find_commit(*label_to_commit_oid.get("B").unwrap())?;
+
+        // WHEN we change only the committer identity of B.
+        repo.regraph(
+            RefArg::AllLocalRefs,
+            &original_b,
+            CommitEdit::new().edit_committer_identity("New committer", "new@example.com"),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+        let new_b = commits.get("B").unwrap();
+
+        // THEN the committer identity is changed.
+        assert_eq!(new_b.committer().name().unwrap(), "New committer");
+        assert_eq!(new_b.committer().email().unwrap(), "new@example.com");
+
+        // THEN the commit time is preserved.
+        assert_eq!(
+            new_b.committer().when().seconds(),
+            original_b.committer().when().seconds(),
+            "B's committer date should be untouched"
+        );
+        assert_eq!(
+            new_b.committer().when().offset_minutes(),
+            original_b.committer().when().offset_minutes(),
+            "B's committer date should be untouched"
+        );
+
+        // THEN the author is untouched.
+        assert_eq!(
+            new_b.author().name().unwrap(),
+            original_b.author().name().unwrap(),
+            "B's author should be untouched"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_updates_detached_worktree_heads() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit whose tree we'll reuse.
+                ("C", 2, &["B"]), // With a tip commit to be edited.
+            ],
+            &[("master", "C"), ("feature", "C")],
+        )?;
+        let worktrees_dir = tempdir()?;
+
+        // GIVEN a linked worktree with a detached HEAD at the commit to be edited.
+        let detached_path = worktrees_dir.path().join("detached");
+        let detached_worktree = repo.worktree("detached", &detached_path, None)?;
+        Repository::open_from_worktree(&detached_worktree)?
+            .set_head_detached(*label_to_commit_oid.get("C").unwrap())?;
+
+        // GIVEN a linked worktree with a branch checked out at the commit to be edited.
+        let feature_path = worktrees_dir.path().join("feature");
+        let feature_branch = repo.find_reference("refs/heads/feature")?;
+        let mut worktree_options = WorktreeAddOptions::new();
+        worktree_options.reference(Some(&feature_branch));
+        repo.worktree("feature", &feature_path, Some(&worktree_options))?;
+        pause("Created repo")?;
+
+        // WHEN we change the tree of C.
+        let b_tree = repo
+            .find_commit(*label_to_commit_oid.get("B").unwrap())?
+            .tree()?;
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_tree(&b_tree),
+        )?;
+        pause("Regraph complete")?;
+        let new_c = *report
+            .old_to_new_oids
+            .get(label_to_commit_oid.get("C").unwrap())
+            .unwrap();
+
+        // THEN the detached worktree HEAD follows the rewritten commit.
+        let detached_repo = Repository::open_from_worktree(&detached_worktree)?;
+        assert!(detached_repo.head_detached()?, "HEAD should still be detached");
+        assert_eq!(
+            detached_repo.head()?.target(),
+            Some(new_c),
+            "Detached HEAD should point to the rewritten 'C'"
+        );
+
+        // THEN the branch checked out in the other worktree is updated.
+        assert_eq!(
+            repo.find_reference("refs/heads/feature")?.target(),
+            Some(new_c),
+            "feature should point to the rewritten 'C'"
+        );
+
+        // THEN both worktrees are warned about, as their working trees no longer match HEAD.
+        for path in &[&detached_path, &feature_path] {
+            assert!(
+                report
+                    .warnings
+                    .iter()
+                    .any(|warning| warning.contains(&*path.display().to_string())),
+                "There should be a warning about the worktree at {}",
+                path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_updates_the_main_worktree_from_a_linked_worktree() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a tip commit to be edited.
+            ],
+            &[("feature", "B")],
+        )?;
+        // With the main worktree's HEAD detached at the commit to be edited.
+        repo.set_head_detached(*label_to_commit_oid.get("B").unwrap())?;
+        // With a linked worktree to run the regraph from.
+        let worktrees_dir = tempdir()?;
+        let mut worktree_options = WorktreeAddOptions::new();
+        let feature_branch = repo.find_reference("refs/heads/feature")?;
+        worktree_options.reference(Some(&feature_branch));
+        let linked_worktree = repo.worktree(
+            "linked",
+            &worktrees_dir.path().join("linked"),
+            Some(&worktree_options),
+        )?;
+        let linked_repo = Repository::open_from_worktree(&linked_worktree)?;
+
+        // WHEN B is edited from the linked worktree.
+        let report = linked_repo.regraph(
+            RefArg::AllLocalRefs,
+            &linked_repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        )?;
+
+        // THEN the detached HEAD of the main worktree follows the rewritten commit.
+        let new_b = *report
+            .old_to_new_oids
+            .get(label_to_commit_oid.get("B").unwrap())
+            .unwrap();
+        assert_eq!(Repository::open(repo.path())?.head()?.target(), Some(new_b));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_reset_the_index_of_a_rewritten_head() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit whose tree we'll reuse.
+                ("C", 2, &["B"]), // With a tip commit checked out, to be edited.
+            ],
+            &[("master", "C")],
+        )?;
+
+        // WHEN we change the tree of C, resetting the index.
+        let b_tree = repo
+            .find_commit(*label_to_commit_oid.get("B").unwrap())?
+            .tree()?;
+        let report = Regraph::new(&repo).reset_head(ResetType::Mixed).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_tree(&b_tree),
+        )?;
+
+        // THEN the index matches the tree of the rewritten HEAD.
+        let mut index = Repository::open(repo.path())?.index()?;
+        assert_eq!(index.write_tree()?, b_tree.id());
+
+        // THEN there is nothing to warn about.
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_when_commit_is_not_reachable() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a branch tip.
+                ("X", 2, &[]),    // With an unrelated commit that no ref can reach.
+            ],
+            &[("master", "B")],
+        )?;
+
+        // WHEN we edit the unrelated commit.
+        let x = *label_to_commit_oid.get("X").unwrap();
+        let result = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(x)?,
+            CommitEdit::new().edit_message("Edited X"),
+        );
+
+        // THEN the regraph is rejected, naming the unreachable commit.
+        assert!(
+            matches!(result, Err(RegraphError::CommitNotReachable { commit }) if commit == x),
+            "Editing a commit outside of the updated refs should be rejected"
+        );
+
+        // THEN the ref is left untouched.
+        assert_eq!(
+            repo.find_reference("refs/heads/master")?.target().unwrap(),
+            *label_to_commit_oid.get("B").unwrap(),
+            "master should still point to the original 'B'"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_preserves_message_encoding() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]), // With a root commit to be edited.
+            ],
+            &[("master", "A")],
+        )?;
+        let a = repo.find_commit(*label_to_commit_oid.get("A").unwrap())?;
+
+        // GIVEN a commit with a message that declares an encoding.
+        let signature = Signature::new("B-author", "B-email", &Time::new(1, 0))?;
+        let mut buffer = repo
+            .commit_create_buffer(&signature, &signature, "B", &a.tree()?, &[&a])?
+            .to_vec();
+        let header_end = buffer.windows(2).position(|window| window == b"\n\n").unwrap() + 1;
+        buffer.splice(header_end..header_end, b"encoding ISO-8859-1\n".iter().copied());
+        let b = repo.find_commit(repo.odb()?.write(ObjectType::Commit, &buffer)?)?;
+        assert_eq!(b.message_encoding(), Some("ISO-8859-1"));
+
+        // GIVEN a descendant on master.
+        let c = repo.commit(None, &signature, &signature, "C", &b.tree()?, &[&b])?;
+        repo.reference("refs/heads/master", c, true, "Add commits with encodings")?;
+
+        // WHEN A is edited, so that B is rewritten only because its parent changed.
+        repo.regraph(
+            RefArg::AllLocalRefs,
+            &a,
+            CommitEdit::new().edit_message("Edited A"),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN B keeps its encoding and message.
+        let new_b = commits.get("B").unwrap();
+        assert_ne!(new_b.id(), b.id(), "Commit 'B' should be rewritten");
+        assert_eq!(new_b.message_encoding(), Some("ISO-8859-1"));
+        assert_eq!(new_b.message_raw_bytes(), b.message_raw_bytes());
+
+        // THEN C, which has no encoding, still has none.
+        assert_eq!(commits.get("C").unwrap().message_encoding(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_rewrite_stash_entries() -> Result<()> {
+        fn stash_list(repo: &mut Repository) -> Result<Vec<(String, Oid)>> {
+            let mut entries = Vec::new();
+            repo.stash_foreach(|_, message, oid| {
+                entries.push((message.to_owned(), *oid));
+                true
+            })?;
+            Ok(entries)
+        }
+
+        // GIVEN a repo...
+        let (mut repo, label_to_commit_oid, dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With the commit the stash entries are based on.
+            ],
+            &[("master", "C")],
+        )?;
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+
+        // GIVEN two stash entries based on C.
+        let stasher = Signature::new("Stasher", "stasher@example.com", &Time::new(5, 0))?;
+        for change in &["first", "second"] {
             let mut shared = File::create(dir.path().join("shared"))?;
-            writeln!(shared, "{}", label)?;
-            index.add_all(&["."], IndexAddOption::DEFAULT, None)?;
-            let email = format!("{}-email", label);
-            let time = Time::new(time_sec, 0);
-            let author = Signature::new(&format!("{}-author", label), &email, &time)?;
-            let committer = Signature::new(&format!("{}-comitter", label), &email, &time)?;
-            let tree = repo.find_tree(index.write_tree()?)?;
-            let parents_refs: Vec<&Commit> = parents.iter().collect();
-            Ok(repo.commit(None, &author, &committer, label, &tree, &parents_refs)?)
+            writeln!(shared, "{}", change)?;
+            repo.stash_save(&stasher, change, None)?;
+        }
+        let old_stash_list = stash_list(&mut repo)?;
+        pause("Created repo")?;
+
+        // WHEN B is edited, opting in to rewriting the stash.
+        Regraph::new(&repo).rewrite_stash(true).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        )?;
+        pause("Regraph complete")?;
+        let new_c = repo.find_reference("refs/heads/master")?.target().unwrap();
+
+        // THEN the stash list keeps the same entries, in the same order.
+        let new_stash_list = stash_list(&mut repo)?;
+        assert_eq!(
+            new_stash_list
+                .iter()
+                .map(|(message, _)| message)
+                .collect::<Vec<_>>(),
+            old_stash_list
+                .iter()
+                .map(|(message, _)| message)
+                .collect::<Vec<_>>(),
+            "The stash list should keep the same entries"
+        );
+
+        // THEN every stash entry is based on the rewritten C.
+        for (message, oid) in &new_stash_list {
+            assert_eq!(
+                repo.find_commit(*oid)?.parent_id(0)?,
+                new_c,
+                "Stash entry '{}' should be based on the rewritten 'C'",
+                message
+            );
+        }
+
+        // THEN refs/stash points to the latest rewritten stash entry.
+        assert_eq!(
+            repo.find_reference(STASH_REF)?.target(),
+            Some(new_stash_list[0].1),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_pack_rewritten_objects() -> Result<()> {
+        fn count_loose_objects(repo: &Repository) -> Result<usize> {
+            let mut count = 0;
+            for entry in std::fs::read_dir(repo.path().join("objects"))? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+                    count += std::fs::read_dir(entry.path())?.count();
+                }
+            }
+            Ok(count)
+        }
+
+        // GIVEN a long linear history, in two identical repos.
+        let labels: Vec<String> = (0..30).map(|i| format!("commit-{}", i)).collect();
+        let label_refs: Vec<&str> = labels.iter().map(String::as_str).collect();
+        let graph: Vec<(&str, i64, &[&str])> = label_refs
+            .iter()
+            .enumerate()
+            .map(|(i, label)| (*label, i as i64, &label_refs[i.saturating_sub(1)..i]))
+            .collect();
+        let mut results = Vec::new();
+        for pack_objects in &[false, true] {
+            let (repo, label_to_commit_oid, _dir) =
+                given_repository(&graph, &[("master", "commit-29")])?;
+            let loose_objects_before = count_loose_objects(&repo)?;
+
+            // WHEN the root is edited, rewriting every commit, with and without packing.
+            Regraph::new(&repo).pack_objects(*pack_objects).run(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(*label_to_commit_oid.get("commit-0").unwrap())?,
+                CommitEdit::new().edit_message("Edited commit-0"),
+            )?;
+
+            results.push((
+                count_loose_objects(&repo)? - loose_objects_before,
+                repo.find_reference("refs/heads/master")?.target().unwrap(),
+            ));
+        }
+        let (unpacked_new_loose_objects, unpacked_master) = results[0];
+        let (packed_new_loose_objects, packed_master) = results[1];
+
+        // THEN without packing, each rewritten commit is a new loose object.
+        assert_eq!(unpacked_new_loose_objects, 30);
+
+        // THEN with packing, no new loose objects are written.
+        assert_eq!(packed_new_loose_objects, 0);
+
+        // THEN both rewrites produce the same history.
+        assert_eq!(packed_master, unpacked_master);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_combine_options_through_the_builder() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit that will be dropped.
+                ("C", 2, &["B"]), // With a tip commit.
+            ],
+            &[("master", "C")],
+        )?;
+
+        // WHEN we drop A-B by removing the parents of C, allowing non-fast-forwards and packing
+        // the rewritten objects.
+        let mut regraph = Regraph::new(&repo);
+        regraph.allow_non_fast_forward(true).pack_objects(true);
+        regraph.run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_parents(&[]),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN master is moved to the rewritten 'C', which is now the only commit.
+        assert_eq!(commits.len(), 1, "Only 'C' should remain in the graph");
+        let new_c = commits.get("C").unwrap();
+        assert_eq!(new_c.parent_count(), 0, "Commit 'C' should have no parent.");
+
+        // THEN the rewritten 'C' was written into a pack rather than as a loose object.
+        let hex = new_c.id().to_string();
+        assert!(
+            !repo.path().join("objects").join(&hex[..2]).join(&hex[2..]).exists(),
+            "Commit 'C' should not be a loose object"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_customise_the_reflog() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With a commit to be updated.
+            ],
+            &[("master", "C")],
+        )?;
+        let old_b = *label_to_commit_oid.get("B").unwrap();
+
+        // WHEN we edit B with a custom reflog message and identity.
+        let auditor = Signature::new("Auditor", "auditor@example.com", &Time::new(42, 60))?;
+        let report = Regraph::new(&repo)
+            .reflog_message("audit: rewrote {old} as {new}")
+            .reflog_signature(&auditor)
+            .run(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(old_b)?,
+                CommitEdit::new().edit_message("Edited B"),
+            )?;
+        let new_b = *report.old_to_new_oids.get(&old_b).unwrap();
+
+        // THEN the latest reflog entry of master records the custom message and identity.
+        let reflog = repo.reflog("refs/heads/master")?;
+        let entry = reflog.get(0).unwrap();
+        assert_eq!(
+            entry.message(),
+            Some(format!("audit: rewrote {} as {}", old_b, new_b).as_str())
+        );
+        assert_eq!(entry.committer().name(), Some("Auditor"));
+        assert_eq!(entry.committer().email(), Some("auditor@example.com"));
+        assert_eq!(entry.committer().when(), auditor.when());
+
+        // THEN the entry records the move of master to the rewritten C.
+        assert_eq!(
+            entry.id_old(),
+            *label_to_commit_oid.get("C").unwrap(),
+            "The reflog entry should move master from the original C"
+        );
+        assert_eq!(
+            Some(entry.id_new()),
+            repo.find_reference("refs/heads/master")?.target()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_dedupes_merge_parents_that_converge() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),         // With a root commit.
+                ("B", 1, &["A"]),      // With a commit to be edited.
+                ("C", 2, &["A"]),      // With a sibling commit.
+                ("D", 3, &["B", "C"]), // With a merge of both.
+            ],
+            &[("master", "D")],
+        )?;
+
+        // WHEN B is edited to become identical to C.
+        let c = repo.find_commit(*label_to_commit_oid.get("C").unwrap())?;
+        let c_tree = c.tree()?;
+        let c_author = c.author();
+        let c_committer = c.committer();
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new()
+                .edit_message("C")
+                .edit_tree(&c_tree)
+                .edit_author(&c_author)
+                .edit_committer(&c_committer),
+        )?;
+        assert_eq!(
+            report.old_to_new_oids.get(label_to_commit_oid.get("B").unwrap()),
+            Some(&c.id()),
+            "The edited B should be the same commit as C"
+        );
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN the rewritten merge keeps C as its only parent.
+        let new_d = commits.get("D").unwrap();
+        assert_ne!(new_d.id(), *label_to_commit_oid.get("D").unwrap());
+        assert_eq!(new_d.parent_ids().collect::<Vec<_>>(), vec![c.id()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_verifies_a_regraph() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),         // With a root commit.
+                ("B", 1, &["A"]),      // With a commit to be edited.
+                ("C", 2, &["B"]),      // With a commit to be updated.
+                ("D", 3, &["B"]),      // With a commit on another branch.
+                ("E", 4, &["C", "D"]), // With a merge to be updated.
+            ],
+            &[("master", "E"), ("feature", "D")],
+        )?;
+
+        // WHEN B is edited, and the result is verified.
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        )?;
+
+        // THEN no updated ref can reach an old commit.
+        repo.regraph_verify(&report)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_verification_when_a_ref_is_stale() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With a commit to be updated.
+            ],
+            &[("master", "C"), ("stale", "C")],
+        )?;
+
+        // WHEN B is edited without updating one of the refs, which is then verified as if it
+        // had been updated.
+        let mut report = repo.regraph(
+            RefArg::Refs(vec![repo.find_reference("refs/heads/master")?]),
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        )?;
+        report.updated_refs.push("refs/heads/stale".to_owned());
+        let result = repo.regraph_verify(&report);
+
+        // THEN the verification fails, naming the stale ref and the first old commit it reaches.
+        let c = *label_to_commit_oid.get("C").unwrap();
+        assert!(
+            matches!(
+                &result,
+                Err(RegraphError::StaleCommit { reference, commit })
+                    if reference == "refs/heads/stale" && *commit == c
+            ),
+            "Expected a stale commit error, got {:?}",
+            result
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_collapses_identical_branches_into_a_shared_commit() -> Result<()> {
+        // GIVEN a repo where every commit shares the same tree, author and committer...
+        let dir = tempdir()?;
+        let repo = Repository::init(&dir)?;
+        let tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+        let signature = Signature::new("Author", "author@example.com", &Time::new(0, 0))?;
+        // With a root commit.
+        let r = repo.find_commit(repo.commit(None, &signature, &signature, "R", &tree, &[])?)?;
+        // With a commit identical to the root but for its parent.
+        let e = repo.find_commit(repo.commit(None, &signature, &signature, "R", &tree, &[&r])?)?;
+        // With two branch tips identical but for their parents.
+        let tip_1 = repo.commit(None, &signature, &signature, "Tip", &tree, &[&e])?;
+        let tip_2 = repo.commit(None, &signature, &signature, "Tip", &tree, &[&r, &e])?;
+        repo.branch("master", &repo.find_commit(tip_1)?, false)?;
+        repo.branch("other", &repo.find_commit(tip_2)?, false)?;
+
+        // WHEN the parent of the shared ancestor is cleared, making it identical to the root.
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &e,
+            CommitEdit::new().edit_parents(&[]),
+        )?;
+
+        // THEN both tips collapse into the same rewritten commit, based on the root.
+        let new_tip_1 = repo.find_reference("refs/heads/master")?.target().unwrap();
+        let new_tip_2 = repo.find_reference("refs/heads/other")?.target().unwrap();
+        assert_eq!(new_tip_1, new_tip_2, "Both branches should end at the same commit");
+        assert_ne!(new_tip_1, tip_1);
+        assert_ne!(new_tip_1, tip_2);
+        assert_eq!(
+            repo.find_commit(new_tip_1)?.parent_ids().collect::<Vec<_>>(),
+            vec![r.id()]
+        );
+
+        // THEN each old commit maps to its collapsed replacement.
+        assert_eq!(report.old_to_new_oids.get(&e.id()), Some(&r.id()));
+        assert_eq!(report.old_to_new_oids.get(&tip_1), Some(&new_tip_1));
+        assert_eq!(report.old_to_new_oids.get(&tip_2), Some(&new_tip_1));
+        assert_eq!(report.rewritten_descendants, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_leaves_refs_untouched_in_a_dry_run() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With a commit to be updated.
+            ],
+            &[("master", "C")],
+        )?;
+
+        // WHEN B is edited in a dry run.
+        let report = Regraph::new(&repo).dry_run(true).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        )?;
+
+        // THEN the report describes the rewrite.
+        assert_eq!(report.old_to_new_oids.len(), 2);
+        assert_eq!(report.updated_refs, vec!["refs/heads/master".to_owned()]);
+
+        // THEN master is left untouched.
+        assert_eq!(
+            repo.find_reference("refs/heads/master")?.target(),
+            Some(*label_to_commit_oid.get("C").unwrap())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_preserves_extra_headers() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]), // With a root commit to be edited.
+            ],
+            &[],
+        )?;
+        let a = repo.find_commit(*label_to_commit_oid.get("A").unwrap())?;
+        // With a signed commit with a custom header, to be updated.
+        let b = repo.odb()?.write(
+            ObjectType::Commit,
+            format!(
+                "tree {}\n\
+                 parent {}\n\
+                 author B <b@example.com> 1 +0000\n\
+                 committer B <b@example.com> 1 +0000\n\
+                 x-custom first line\n second line\n\
+                 gpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\
+                 \n\
+                 B",
+                a.tree_id(),
+                a.id()
+            )
+            .as_bytes(),
+        )?;
+        repo.branch("master", &repo.find_commit(b)?, false)?;
+
+        // WHEN A is edited, so that only the parent of B changes.
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &a,
+            CommitEdit::new().edit_message("Edited A"),
+        )?;
+        let new_b = repo.find_commit(*report.old_to_new_oids.get(&b).unwrap())?;
+
+        // THEN the custom header survives the rewrite.
+        assert_eq!(
+            &*new_b.header_field_bytes("x-custom")?,
+            b"first line\nsecond line"
+        );
+
+        // THEN the signature, which no longer verifies, is dropped.
+        assert!(new_b.header_field_bytes("gpgsig").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_add_headers_through_a_commit_hook() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With a descendant to be updated.
+            ],
+            &[("master", "C")],
+        )?;
+
+        // WHEN B is edited, with a hook adding a Change-Id header.
+        let report = Regraph::new(&repo)
+            .commit_hook(|buffer| {
+                buffer
+                    .extra_headers
+                    .push(("change-id".to_owned(), b"I0123456789abcdef".to_vec()))
+            })
+            .run(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+                CommitEdit::new().edit_message("Edited B"),
+            )?;
+
+        // THEN both the edited commit and its descendant carry the header.
+        for label in &["B", "C"] {
+            let new_oid = report
+                .old_to_new_oids
+                .get(label_to_commit_oid.get(label).unwrap())
+                .unwrap();
+            let raw_commit = repo.odb()?.read(*new_oid)?;
+            let raw_commit = String::from_utf8_lossy(raw_commit.data());
+            assert!(
+                raw_commit.contains("\nchange-id I0123456789abcdef\n"),
+                "Commit {} should have a Change-Id header, got: {}",
+                label,
+                raw_commit
+            );
         }
+
+        // THEN commits that weren't rewritten are left alone.
+        assert!(!report
+            .old_to_new_oids
+            .contains_key(label_to_commit_oid.get("A").unwrap()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_commits_whose_content_is_unchanged() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]), // With a root commit.
+            ],
+            &[],
+        )?;
+        let a = repo.find_commit(*label_to_commit_oid.get("A").unwrap())?;
+        // With a signed commit to be edited.
+        let b = repo.odb()?.write(
+            ObjectType::Commit,
+            format!(
+                "tree {}\n\
+                 parent {}\n\
+                 author B <b@example.com> 1 +0000\n\
+                 committer B <b@example.com> 1 +0000\n\
+                 gpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\
+                 \n\
+                 B",
+                a.tree_id(),
+                a.id()
+            )
+            .as_bytes(),
+        )?;
+        repo.branch("master", &repo.find_commit(b)?, false)?;
+
+        // WHEN B is edited in a way that keeps its parents and all of its content.
+        let result = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(b)?,
+            CommitEdit::new().edit_message("B"),
+        );
+
+        // THEN B is not rewritten just to drop its signature.
+        assert!(
+            matches!(result, Err(RegraphError::NoChange)),
+            "Expected no change, got {:?}",
+            result
+        );
+        assert_eq!(repo.find_reference("refs/heads/master")?.target(), Some(b));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_substitute_text_in_messages() -> Result<()> {
+        // GIVEN a repo with a chain of commits mentioning an old ticket prefix.
         let dir = tempdir()?;
         let repo = Repository::init(&dir)?;
-        let mut index = repo.index()?;
-        let mut label_to_commit_oid = HashMap::new();
-        for (label, time, parents) in graph {
+        let tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+        let signature = Signature::new("Author", "author@example.com", &Time::new(0, 0))?;
+        let messages = ["OLD-1: Add A", "OLD-2: Add B", "OLD-3: Add C"];
+        let mut old_oids = Vec::new();
+        for message in messages.iter() {
+            let parents = old_oids
+                .last()
+                .map(|oid| repo.find_commit(*oid))
+                .transpose()?;
+            let parent_refs: Vec<&Commit> = parents.iter().collect();
+            let oid = repo.commit(None, &signature, &signature, message, &tree, &parent_refs)?;
+            old_oids.push(oid);
+        }
+        repo.branch("master", &repo.find_commit(old_oids[2])?, false)?;
+
+        // WHEN the prefix is substituted, starting from the root.
+        let report = Regraph::new(&repo).substitute_message("OLD-", "NEW-").run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(old_oids[0])?,
+            &CommitEdit::new(),
+        )?;
+
+        // THEN every commit in the chain is rewritten with the new prefix.
+        assert_eq!(report.old_to_new_oids.len(), 3);
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push_ref("refs/heads/master")?;
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        let messages = revwalk
+            .map(|oid| -> Result<String> {
+                Ok(repo.find_commit(oid?)?.message().unwrap().to_owned())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(messages, vec!["NEW-1: Add A", "NEW-2: Add B", "NEW-3: Add C"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_when_a_ref_is_unresolvable() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+            ],
+            &[("master", "B")],
+        )?;
+        // With a symbolic ref to a ref that doesn't exist.
+        repo.reference_symbolic("refs/heads/dangling", "refs/heads/missing", false, "")?;
+
+        // WHEN B is edited, updating all local refs.
+        let result = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        );
+
+        // THEN the regraph fails, naming the dangling ref.
+        assert!(
+            matches!(
+                &result,
+                Err(RegraphError::UnresolvableReference { reference })
+                    if reference == "refs/heads/dangling"
+            ),
+            "Expected an unresolvable reference error, got {:?}",
+            result
+        );
+
+        // THEN master is left untouched.
+        assert_eq!(
+            repo.find_reference("refs/heads/master")?.target(),
+            Some(*label_to_commit_oid.get("B").unwrap())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_names_the_ref_that_failed_to_update() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+            ],
+            &[("master", "B"), ("feature", "B")],
+        )?;
+        // With a ref locked by another process.
+        std::fs::write(repo.path().join("refs/heads/feature.lock"), "")?;
+
+        // WHEN B is edited, updating all local refs.
+        let result = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        );
+
+        // THEN the regraph fails, naming the locked ref.
+        assert!(
+            matches!(
+                &result,
+                Err(RegraphError::RefUpdateFailed { reference, .. })
+                    if reference == "refs/heads/feature"
+            ),
+            "Expected a ref update failure, got {:?}",
+            result
+        );
+
+        // THEN the locked ref is left untouched.
+        assert_eq!(
+            repo.find_reference("refs/heads/feature")?.target(),
+            Some(*label_to_commit_oid.get("B").unwrap())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_orphaned_commits() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),         // With main root.
+                ("B", 1, &[]),         // With subtree root.
+                ("C", 2, &["B"]),      // With more than one commit in subtree.
+                ("D", 3, &["A", "C"]), // With subtree merged into main.
+                ("E", 4, &["D"]),      // With commit after merge.
+            ],
+            &[("master", "E")],
+        )?;
+
+        // WHEN we squash B-C by removing parents of C.
+        let report = Regraph::new(&repo).allow_non_fast_forward(true).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_parents(&[]),
+        )?;
+
+        // THEN only B is reported as orphaned, since the other commits are either untouched or
+        // were rewritten.
+        assert_eq!(
+            report.orphaned_commits,
+            vec![*label_to_commit_oid.get("B").unwrap()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_edit_a_range_of_commits() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a base commit to be left untouched.
+                ("B", 1, &["A"]), // With commits to be edited.
+                ("C", 2, &["B"]),
+                ("D", 3, &["C"]), // With a commit past the range to be updated.
+            ],
+            &[("master", "D")],
+        )?;
+
+        // WHEN the committer identity is normalised over A..C.
+        Regraph::new(&repo).run_range(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("A").unwrap())?,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_committer_identity("Normalised", "normalised@example.com"),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN the base commit is untouched.
+        assert_eq!(
+            commits.get("A").unwrap().id(),
+            *label_to_commit_oid.get("A").unwrap()
+        );
+
+        // THEN each commit in the range has the normalised committer, keeping its commit time.
+        for (label, time) in &[("B", 1), ("C", 2)] {
+            let committer = commits.get(*label).unwrap().committer();
+            assert_eq!(committer.name(), Some("Normalised"));
+            assert_eq!(committer.email(), Some("normalised@example.com"));
+            assert_eq!(committer.when().seconds(), *time);
+        }
+
+        // THEN the commit past the range is rewritten onto the range, keeping its committer.
+        let d = commits.get("D").unwrap();
+        assert_ne!(d.id(), *label_to_commit_oid.get("D").unwrap());
+        assert_eq!(d.committer().name(), Some("D-comitter"));
+        assert_eq!(d.parent_id(0)?, commits.get("C").unwrap().id());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_discovers_commits_in_a_stable_order() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),         // With a root commit to be edited.
+                ("B", 1, &["A"]),      // With two sibling commits, tied in topological order.
+                ("C", 2, &["A"]),
+                ("D", 3, &["B", "C"]), // With a merge of both.
+                ("E", 4, &["D"]),      // With a tip commit.
+            ],
+            &[("master", "E")],
+        )?;
+
+        // WHEN the same edit is worked out repeatedly.
+        let a = repo.find_commit(*label_to_commit_oid.get("A").unwrap())?;
+        let mut discovered = Vec::new();
+        for _ in 0..3 {
+            let report = Regraph::new(&repo).dry_run(true).run(
+                RefArg::AllLocalRefs,
+                &a,
+                CommitEdit::new().edit_message("Edited A"),
+            )?;
+            discovered.push(report.discovered_commits);
+        }
+
+        // THEN the commits are discovered in the same order each time, parents first and
+        // oldest first.
+        let expected: Vec<Oid> = ["A", "B", "C", "D", "E"]
+            .iter()
+            .map(|label| *label_to_commit_oid.get(label).unwrap())
+            .collect();
+        for order in &discovered {
+            assert_eq!(order, &expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_only_walks_refs_containing_the_edited_commit() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With two branches containing the edited commit.
+                ("D", 3, &["B"]),
+                ("E", 4, &["A"]), // With a branch sharing only the root.
+                ("F", 5, &[]),    // With a branch sharing no history at all.
+                ("G", 6, &["F"]),
+            ],
+            &[
+                ("master", "C"),
+                ("feature", "D"),
+                ("sibling", "E"),
+                ("unrelated", "G"),
+            ],
+        )?;
+
+        // WHEN B is edited, updating all local refs.
+        let report = Regraph::new(&repo).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        )?;
+
+        // THEN only the branches containing B are walked and moved.
+        for label in &["E", "F", "G"] {
             assert!(
-                !label_to_commit_oid.contains_key(label),
-                "No duplicate commit labels"
+                !report
+                    .discovered_commits
+                    .contains(label_to_commit_oid.get(label).unwrap()),
+                "Commit {} should not be walked",
+                label
             );
+        }
+        let mut updated_refs = report.updated_refs.clone();
+        updated_refs.sort();
+        assert_eq!(updated_refs, vec!["refs/heads/feature", "refs/heads/master"]);
+        assert_eq!(
+            repo.find_reference("refs/heads/sibling")?.target(),
+            Some(*label_to_commit_oid.get("E").unwrap())
+        );
+        assert_eq!(
+            repo.find_reference("refs/heads/unrelated")?.target(),
+            Some(*label_to_commit_oid.get("G").unwrap())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_edit_the_message_of_a_merge() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),         // With a root commit.
+                ("B", 1, &["A"]),      // With two branches.
+                ("C", 2, &["A"]),
+                ("D", 3, &["B", "C"]), // With a merge to be edited.
+                ("E", 4, &["D"]),      // With two descendants to be updated.
+                ("F", 5, &["D"]),
+            ],
+            &[("master", "E"), ("other", "F")],
+        )?;
+
+        // WHEN only the message of the merge is edited.
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("D").unwrap())?,
+            CommitEdit::new().edit_message("Edited D"),
+        )?;
+        let new_d = repo.find_commit(
+            *report
+                .old_to_new_oids
+                .get(label_to_commit_oid.get("D").unwrap())
+                .unwrap(),
+        )?;
+
+        // THEN the rewritten merge keeps both parents, in their original order.
+        assert_eq!(new_d.message(), Some("Edited D"));
+        assert_eq!(
+            new_d.parent_ids().collect::<Vec<_>>(),
+            vec![
+                *label_to_commit_oid.get("B").unwrap(),
+                *label_to_commit_oid.get("C").unwrap(),
+            ]
+        );
+
+        // THEN both descendants are rewritten onto the rewritten merge.
+        assert_eq!(report.rewritten_descendants, 2);
+        for (branch, label) in &[("master", "E"), ("other", "F")] {
+            let tip = repo.find_branch(branch, BranchType::Local)?.get().peel_to_commit()?;
+            assert_ne!(tip.id(), *label_to_commit_oid.get(label).unwrap());
+            assert_eq!(tip.message(), Some(*label));
+            assert_eq!(tip.parent_ids().collect::<Vec<_>>(), vec![new_d.id()]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rewrites_descendants_onto_recorded_parents() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),         // With a root commit to be edited.
+                ("B", 1, &["A"]),      // With a diamond of descendants.
+                ("C", 2, &["A"]),
+                ("D", 3, &["B", "C"]),
+                ("E", 4, &["D"]),
+            ],
+            &[("master", "E")],
+        )?;
 
-            let mut parent_commits = Vec::new();
-            for parent in *parents {
-                parent_commits.push(repo.find_commit(*label_to_commit_oid.get(parent).unwrap())?);
-            }
+        // WHEN A is edited.
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("A").unwrap())?,
+            CommitEdit::new().edit_message("Edited A"),
+        )?;
 
-            let commit_oid = add_commit(&dir, &mut index, &repo, label, *time, &parent_commits)?;
+        // THEN every commit is rewritten.
+        assert_eq!(report.old_to_new_oids.len(), 5);
 
-            label_to_commit_oid.insert(*label, commit_oid);
+        // THEN each rewritten commit keeps its own content, with its parents replaced by
+        // exactly the rewritten parents recorded in the report.
+        for (old_oid, new_oid) in &report.old_to_new_oids {
+            let old_commit = repo.find_commit(*old_oid)?;
+            let new_commit = repo.find_commit(*new_oid)?;
+            assert_eq!(new_commit.tree_id(), old_commit.tree_id());
+            let expected_parents: Vec<Oid> = old_commit
+                .parent_ids()
+                .map(|oid| *report.old_to_new_oids.get(&oid).unwrap())
+                .collect();
+            assert_eq!(new_commit.parent_ids().collect::<Vec<_>>(), expected_parents);
         }
-        for (branch_name, target) in branches {
-            let commit = repo.find_commit(*label_to_commit_oid.get(target).unwrap())?;
-            repo.branch(branch_name, &commit, true)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "gix-backend")]
+    #[test]
+    fn it_writes_the_same_commits_with_either_backend() -> Result<()> {
+        let mut results = Vec::new();
+        for gix_backend in &[false, true] {
+            // GIVEN a repo...
+            let (repo, label_to_commit_oid, _dir) = given_repository(
+                &[
+                    ("A", 0, &[]),         // With a root commit to be edited.
+                    ("B", 1, &["A"]),      // With a diamond of descendants.
+                    ("C", 2, &["A"]),
+                    ("D", 3, &["B", "C"]),
+                    ("E", 4, &["D"]),
+                ],
+                &[("master", "E")],
+            )?;
+
+            // WHEN A is edited with either backend.
+            let report = Regraph::new(&repo).gix_backend(*gix_backend).run(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(*label_to_commit_oid.get("A").unwrap())?,
+                CommitEdit::new()
+                    .edit_message("Edited A")
+                    .edit_author_identity("New author", "new@example.com"),
+            )?;
+            assert_eq!(report.old_to_new_oids.len(), 5);
+            results.push((
+                report.old_to_new_oids,
+                repo.find_reference("refs/heads/master")?.target().unwrap(),
+            ));
         }
-        Ok((repo, label_to_commit_oid, dir))
+
+        // THEN both backends write identical commits.
+        assert_eq!(results[0], results[1]);
+
+        Ok(())
     }
 
-    fn label_to_commit_reachable_from_ref<'a>(
-        repo: &'a Repository,
-        reference: &str,
-    ) -> Result<HashMap<String, Commit<'a>>> {
-        let mut label_to_commit = HashMap::new();
+    #[test]
+    fn it_lists_the_names_of_refs_matching_a_glob() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, _, _dir) = given_repository(
+            &[
+                ("A", 0, &[]), // With a root commit.
+            ],
+            // With some branches matching a glob, and some not.
+            &[
+                ("master", "A"),
+                ("feature/one", "A"),
+                ("feature/two", "A"),
+                ("featureless", "A"),
+            ],
+        )?;
 
-        let mut revwalk = repo.revwalk()?;
-        revwalk.push_ref(reference)?;
+        // WHEN the refs matching a glob are listed.
+        let ref_arg = RefArg::Glob("refs/heads/feature/*".to_owned());
+        let mut names = ref_arg.names(&repo)?;
+        names.sort();
 
-        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        // THEN only the matching branches are listed.
+        assert_eq!(
+            names,
+            vec!["refs/heads/feature/one", "refs/heads/feature/two"]
+        );
 
-        for found_commit in revwalk {
-            let commit_oid = found_commit?;
-            let commit = repo.find_commit(commit_oid)?;
-            let label = commit.message().unwrap().to_string();
-            label_to_commit.insert(label, commit.clone());
-        }
+        // THEN the same refs are resolved when the RefArg is used afterwards.
+        let mut resolved_names: Vec<String> =
+            ref_arg.resolve(&repo)?.iter().map(reference_name).collect();
+        resolved_names.sort();
+        assert_eq!(resolved_names, names);
 
-        Ok(label_to_commit)
+        Ok(())
     }
 
-    fn pause(message: &str) -> Result<()> {
-        if std::env::var("PAUSE").is_ok() {
-            println!("{} [Press any key to continue]", message);
-            while !matches!(read()?, Event::Key(_)) {}
+    #[test]
+    fn it_can_apply_a_mailmap() -> Result<()> {
+        // GIVEN a repo with a chain of commits...
+        let dir = tempdir()?;
+        let repo = Repository::init(&dir)?;
+        let tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+        let identities = [
+            ("Alice", "alice@old.example.com"), // With an outdated identity.
+            ("alice", "alice@new.example.com"), // With a differently written identity.
+            ("Bob", "bob@example.com"),         // With a canonical identity.
+        ];
+        let mut old_oids = Vec::new();
+        for (time, (name, email)) in identities.iter().enumerate() {
+            let signature = Signature::new(name, email, &Time::new(time as i64, 0))?;
+            let parents = old_oids
+                .last()
+                .map(|oid| repo.find_commit(*oid))
+                .transpose()?;
+            let parent_refs: Vec<&Commit> = parents.iter().collect();
+            let oid = repo.commit(None, &signature, &signature, name, &tree, &parent_refs)?;
+            old_oids.push(oid);
+        }
+        repo.branch("master", &repo.find_commit(old_oids[2])?, false)?;
+
+        // WHEN a mailmap collapsing both of Alice's identities into one is applied from the root.
+        let mailmap = Mailmap::from_buffer(
+            "Alice Smith <alice@example.com> <alice@old.example.com>\n\
+             Alice Smith <alice@example.com> <alice@new.example.com>\n",
+        )?;
+        let report = Regraph::new(&repo).mailmap(mailmap).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(old_oids[0])?,
+            &CommitEdit::new(),
+        )?;
+        let new_commits = old_oids
+            .iter()
+            .map(|oid| repo.find_commit(*report.old_to_new_oids.get(oid).unwrap()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // THEN both of Alice's commits use the canonical identity, keeping their times.
+        for (time, commit) in new_commits[..2].iter().enumerate() {
+            for signature in &[commit.author(), commit.committer()] {
+                assert_eq!(signature.name(), Some("Alice Smith"));
+                assert_eq!(signature.email(), Some("alice@example.com"));
+                assert_eq!(signature.when().seconds(), time as i64);
+            }
         }
+
+        // THEN Bob's commit is rewritten onto Alice's, keeping his identity.
+        assert_eq!(new_commits[2].author().name(), Some("Bob"));
+        assert_eq!(new_commits[2].parent_id(0)?, new_commits[1].id());
+
         Ok(())
     }
 
     #[test]
-    fn it_can_squash_to_root() -> Result<()> {
+    fn it_only_applies_a_mailmap_to_descendants_of_the_edit() -> Result<()> {
         // GIVEN a repo...
         let (repo, label_to_commit_oid, _dir) = given_repository(
             &[
-                ("A", 0, &[]),         // With main root.
-                ("B", 1, &[]),         // With subtree root.
-                ("C", 2, &["B"]),      // With more than one commit in subtree.
-                ("D", 3, &["A", "C"]), // With subtree merged into main.
-                ("E", 4, &["D"]),      // With commit after merge.
+                ("A", 0, &[]),         // With a root commit.
+                ("B", 1, &["A"]),      // With a side branch.
+                ("C", 2, &["A"]),      // With a commit to be edited.
+                ("D", 3, &["C", "B"]), // With a merge of the side branch.
             ],
-            &[("master", "E")],
+            &[("master", "D")],
         )?;
-        pause("Created repo")?;
+        let b = *label_to_commit_oid.get("B").unwrap();
+        let c = *label_to_commit_oid.get("C").unwrap();
+        let d = *label_to_commit_oid.get("D").unwrap();
 
-        // WHEN we squash B-C by removing parents of C.
-        repo.regraph(
+        // WHEN C is edited with a mailmap covering the authors of both B and D.
+        let mailmap = Mailmap::from_buffer(
+            "Canonical <canonical@example.com> <B-email>\n\
+             Canonical <canonical@example.com> <D-email>\n",
+        )?;
+        let report = Regraph::new(&repo).mailmap(mailmap).run(
             RefArg::AllLocalRefs,
-            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
-            CommitEdit::new().edit_parents(&[]),
+            &repo.find_commit(c)?,
+            CommitEdit::new().edit_message("Edited C"),
         )?;
-        pause("Regraph complete")?;
-        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
 
-        // THEN
-        assert_eq!(
-            commits.get("A").unwrap().id(),
-            *label_to_commit_oid.get("A").unwrap(),
-            "Commit 'A' should remain unaffected, since it doesn't depend on 'C'"
+        // THEN the side branch is left as it is.
+        assert!(!report.old_to_new_oids.contains_key(&b));
+
+        // THEN the merge is rewritten with the canonical identity, still merging B.
+        let new_d = repo.find_commit(*report.old_to_new_oids.get(&d).unwrap())?;
+        assert_eq!(new_d.author().name(), Some("Canonical"));
+        assert_eq!(new_d.parent_ids().nth(1), Some(b));
+
+        Ok(())
+    }
+
+    /// Creates a repo with master at the end of a chain of three commits, of which only the
+    /// middle one has a message that is not valid utf-8, returning the commits in order.
+    fn given_repository_with_invalid_utf8_message() -> Result<(Repository, Vec<Oid>, TempDir)> {
+        let (repo, label_to_commit_oid, dir) = given_repository(&[("A", 0, &[])], &[])?;
+        let a = repo.find_commit(*label_to_commit_oid.get("A").unwrap())?;
+        let mut buffer = format!(
+            "tree {}\n\
+             parent {}\n\
+             author B <b@example.com> 1 +0000\n\
+             committer B <b@example.com> 1 +0000\n\
+             \n",
+            a.tree_id(),
+            a.id()
+        )
+        .into_bytes();
+        buffer.extend_from_slice(b"B \xff\xfe");
+        let b = repo.odb()?.write(ObjectType::Commit, &buffer)?;
+        let signature = Signature::new("C", "c@example.com", &Time::new(2, 0))?;
+        let c = repo.commit(
+            None,
+            &signature,
+            &signature,
+            "C",
+            &a.tree()?,
+            &[&repo.find_commit(b)?],
+        )?;
+        repo.branch("master", &repo.find_commit(c)?, false)?;
+        Ok((repo, vec![a.id(), b, c], dir))
+    }
+
+    #[test]
+    fn it_aborts_on_invalid_utf8_messages_by_default() -> Result<()> {
+        // GIVEN a repo with a commit whose message is not valid utf-8.
+        let (repo, oids, _dir) = given_repository_with_invalid_utf8_message()?;
+
+        // WHEN the root is edited.
+        let result = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(oids[0])?,
+            CommitEdit::new().edit_message("Edited A"),
         );
 
-        // THEN
+        // THEN the regraph fails, naming the invalid commit.
         assert!(
-            !commits.contains_key("B"),
-            "Commit 'B' should no longer be in the graph."
+            matches!(
+                &result,
+                Err(RegraphError::CommitWithInvalidUtf8Message { commit, .. }) if *commit == oids[1]
+            ),
+            "Expected an invalid utf-8 error, got {:?}",
+            result
         );
 
-        // THEN
-        assert_eq!(
-            commits.get("C").unwrap().parent_count(),
-            0,
-            "Commit 'C' should have no parent."
-        );
+        // THEN master is left untouched.
+        assert_eq!(repo.find_reference("refs/heads/master")?.target(), Some(oids[2]));
 
-        // THEN
-        assert_eq!(
-            commits.get("D").unwrap().parent_count(),
-            2,
-            "Commit 'D' should still have 2 parents."
-        );
+        Ok(())
+    }
 
-        // THEN
-        assert_eq!(
-            commits.get("D").unwrap().parent_id(0)?,
-            commits.get("A").unwrap().id(),
-            "Commit 'D' should still have 'A' as its first parent."
-        );
+    #[test]
+    fn it_can_pass_through_invalid_utf8_messages() -> Result<()> {
+        // GIVEN a repo with a commit whose message is not valid utf-8.
+        let (repo, oids, _dir) = given_repository_with_invalid_utf8_message()?;
 
-        // THEN
-        assert_eq!(
-            commits.get("D").unwrap().parent_id(1)?,
-            commits.get("C").unwrap().id(),
-            "Commit 'D' should still have 'C' as its second parent."
-        );
+        // WHEN the root is edited, passing through invalid messages.
+        let report = Regraph::new(&repo)
+            .on_invalid_utf8(InvalidUtf8Policy::PassThroughBytes)
+            .run(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(oids[0])?,
+                CommitEdit::new().edit_message("Edited A"),
+            )?;
 
-        // THEN
-        assert!(commits.contains_key("E"), "Commit 'E' is updated");
+        // THEN the invalid commit is rewritten with its original message bytes.
+        let new_b = repo.find_commit(*report.old_to_new_oids.get(&oids[1]).unwrap())?;
+        assert_eq!(new_b.message_bytes(), b"B \xff\xfe");
 
-        // THEN all commits should still have the same trees and signatures.
-        for (label, new_commit) in commits.iter() {
-            let old_commit = repo.find_commit(*label_to_commit_oid.get(label as &str).unwrap())?;
-            assert_eq!(
-                new_commit.tree_id(),
-                old_commit.tree_id(),
-                "{}'s tree should be untouched",
-                label
-            );
-            assert_eq!(
-                new_commit.author().email().unwrap(),
-                old_commit.author().email().unwrap(),
-                "{}'s author email should be untouched",
-                label
-            );
-            assert_eq!(
-                new_commit.author().name().unwrap(),
-                old_commit.author().name().unwrap(),
-                "{}'s author name should be untouched",
-                label
-            );
-            assert_eq!(
-                new_commit.author().when().seconds(),
-                old_commit.author().when().seconds(),
-                "{}'s author date should be untouched",
-                label
-            );
-            assert_eq!(
-                new_commit.author().when().offset_minutes(),
-                old_commit.author().when().offset_minutes(),
-                "{}'s author date should be untouched",
-                label
-            );
+        // THEN master is moved to the rewritten C, based on the rewritten invalid commit.
+        let new_c = repo.find_commit(repo.find_reference("refs/heads/master")?.target().unwrap())?;
+        assert_ne!(new_c.id(), oids[2]);
+        assert_eq!(new_c.parent_id(0)?, new_b.id());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_skip_invalid_utf8_messages() -> Result<()> {
+        // GIVEN a repo with a commit whose message is not valid utf-8.
+        let (repo, oids, _dir) = given_repository_with_invalid_utf8_message()?;
+
+        // WHEN the root is edited, skipping invalid messages.
+        let report = Regraph::new(&repo)
+            .on_invalid_utf8(InvalidUtf8Policy::Skip)
+            .run(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(oids[0])?,
+                CommitEdit::new().edit_message("Edited A"),
+            )?;
+
+        // THEN only the root is rewritten, leaving the invalid commit and its descendant as they
+        // are.
+        assert_eq!(report.old_to_new_oids.len(), 1);
+        assert!(report.old_to_new_oids.contains_key(&oids[0]));
+        assert_eq!(repo.find_reference("refs/heads/master")?.target(), Some(oids[2]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_undo_a_regraph() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With commits on two branches to be updated.
+                ("D", 3, &["B"]),
+            ],
+            &[("master", "C"), ("feature", "D"), ("base", "A")],
+        )?;
+        let original_targets: Vec<(String, Oid)> = RefArg::AllLocalRefs
+            .resolve(&repo)?
+            .iter()
+            .map(|reference| (reference_name(reference), reference.target().unwrap()))
+            .collect();
+
+        // WHEN B is edited, and the regraph is undone.
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        )?;
+        assert_eq!(report.updated_refs.len(), 2);
+        repo.regraph_undo(RefArg::AllLocalRefs, &report.old_to_new_oids)?;
+
+        // THEN every ref is back at its original commit.
+        for (name, target) in &original_targets {
             assert_eq!(
-                new_commit.author().when().sign(),
-                old_commit.author().when().sign(),
-                "{}'s author date should be untouched",
-                label
+                repo.find_reference(name)?.target(),
+                Some(*target),
+                "Ref {} should be restored",
+                name
             );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_only_undoes_the_ref_moves_of_a_regraph() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),         // With a root commit.
+                ("B", 1, &["A"]),      // With a commit to be edited.
+                ("C", 2, &["A"]),      // With a sibling commit.
+                ("D", 3, &["B", "C"]), // With a merge of both.
+            ],
+            &[("master", "D"), ("side", "C")],
+        )?;
+        let c = repo.find_commit(*label_to_commit_oid.get("C").unwrap())?;
+        let d = *label_to_commit_oid.get("D").unwrap();
+
+        // WHEN B is edited to become identical to C, which side stays parked on.
+        let c_tree = c.tree()?;
+        let c_author = c.author();
+        let c_committer = c.committer();
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new()
+                .edit_message("C")
+                .edit_tree(&c_tree)
+                .edit_author(&c_author)
+                .edit_committer(&c_committer),
+        )?;
+        assert_eq!(report.updated_refs, vec!["refs/heads/master".to_owned()]);
+
+        // WHEN the regraph is undone from its map alone.
+        let result = repo.regraph_undo(RefArg::AllLocalRefs, &report.old_to_new_oids);
+
+        // THEN the undo fails, as side can't be told apart from a ref the regraph moved.
+        assert!(
+            matches!(
+                &result,
+                Err(RegraphError::PreexistingUndoTarget { reference, commit })
+                    if reference == "refs/heads/side" && *commit == c.id()
+            ),
+            "Expected the undo to be rejected, got {:?}",
+            result
+        );
+        assert_ne!(repo.find_reference("refs/heads/master")?.target(), Some(d));
+
+        // WHEN the regraph is undone from its report.
+        repo.regraph_undo_report(&report)?;
+
+        // THEN master is restored, and side stays at C.
+        assert_eq!(repo.find_reference("refs/heads/master")?.target(), Some(d));
+        assert_eq!(repo.find_reference("refs/heads/side")?.target(), Some(c.id()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_preserves_first_parents_of_merges() -> Result<()> {
+        for edited_label in &["B", "C"] {
+            // GIVEN a repo...
+            let (repo, label_to_commit_oid, _dir) = given_repository(
+                &[
+                    ("A", 0, &[]),         // With a root commit.
+                    ("B", 1, &["A"]),      // With two branches, either of which is edited.
+                    ("C", 2, &["A"]),
+                    ("D", 3, &["C", "B"]), // With a merge whose first parent is the newer one.
+                ],
+                &[("master", "D")],
+            )?;
+
+            // WHEN one of the merged commits is edited.
+            let report = repo.regraph(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(*label_to_commit_oid.get(edited_label).unwrap())?,
+                CommitEdit::new().edit_message("Edited"),
+            )?;
+
+            // THEN each parent of the rewritten merge is in the same position as the parent it
+            // was rewritten from.
+            let master = repo.find_reference("refs/heads/master")?;
+            let new_d = master.peel_to_commit()?;
+            let expected_parents: Vec<Oid> = ["C", "B"]
+                .iter()
+                .map(|label| {
+                    let oid = label_to_commit_oid.get(label).unwrap();
+                    *report.old_to_new_oids.get(oid).unwrap_or(oid)
+                })
+                .collect();
             assert_eq!(
-                new_commit.committer().email().unwrap(),
-                old_commit.committer().email().unwrap(),
-                "{}'s committer email should be untouched",
-                label
+                new_d.parent_ids().collect::<Vec<_>>(),
+                expected_parents,
+                "Editing {} should keep the parents of D in order",
+                edited_label
             );
             assert_eq!(
-                new_commit.committer().name().unwrap(),
-                old_commit.committer().name().unwrap(),
-                "{}'s committer name should be untouched",
-                label
+                new_d.parent(0)?.message(),
+                Some(if *edited_label == "C" { "Edited" } else { "C" }),
+                "The first parent of D should still be (rewritten) C"
             );
-            assert_eq!(
-                new_commit.committer().when().seconds(),
-                old_commit.committer().when().seconds(),
-                "{}'s committer date should be untouched",
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_stops_rewriting_at_a_boundary() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit to be edited.
+                ("B", 1, &["A"]), // With a boundary commit.
+                ("C", 2, &["B"]), // With a commit past the boundary.
+                ("D", 3, &["A"]), // With a commit on another branch.
+            ],
+            &[("master", "C"), ("feature", "D")],
+        )?;
+
+        // WHEN A is edited, with B as a boundary.
+        let report = Regraph::new(&repo)
+            .boundary(*label_to_commit_oid.get("B").unwrap())
+            .run(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(*label_to_commit_oid.get("A").unwrap())?,
+                CommitEdit::new().edit_message("Edited A"),
+            )?;
+
+        // THEN neither the boundary nor the commit past it are rewritten, and master stays put.
+        for label in &["B", "C"] {
+            assert!(
+                !report
+                    .old_to_new_oids
+                    .contains_key(label_to_commit_oid.get(label).unwrap()),
+                "Commit {} should not be rewritten",
                 label
             );
-            assert_eq!(
-                new_commit.committer().when().offset_minutes(),
-                old_commit.committer().when().offset_minutes(),
-                "{}'s committer date should be untouched",
+        }
+        assert_eq!(
+            repo.find_reference("refs/heads/master")?.target(),
+            Some(*label_to_commit_oid.get("C").unwrap())
+        );
+
+        // THEN the other branch is still rewritten onto the edited commit.
+        let new_d = repo.find_reference("refs/heads/feature")?.peel_to_commit()?;
+        assert_ne!(new_d.id(), *label_to_commit_oid.get("D").unwrap());
+        assert_eq!(new_d.parent(0)?.message(), Some("Edited A"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rewrites_commits_between_the_edit_and_a_boundary() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit to be edited.
+                ("B", 1, &["A"]), // With a commit after the edit.
+                ("C", 2, &["B"]), // With another commit after the edit.
+                ("D", 3, &["C"]), // With a boundary commit.
+                ("E", 4, &["D"]), // With a commit past the boundary.
+            ],
+            &[("master", "E"), ("feature", "C")],
+        )?;
+
+        // WHEN A is edited, with D as a boundary.
+        let report = Regraph::new(&repo)
+            .boundary(*label_to_commit_oid.get("D").unwrap())
+            .run(
+                RefArg::AllLocalRefs,
+                &repo.find_commit(*label_to_commit_oid.get("A").unwrap())?,
+                CommitEdit::new().edit_message("Edited A"),
+            )?;
+
+        // THEN the commits between the edit and the boundary are rewritten.
+        let new_c = repo.find_reference("refs/heads/feature")?.peel_to_commit()?;
+        assert_eq!(
+            report
+                .old_to_new_oids
+                .get(label_to_commit_oid.get("C").unwrap()),
+            Some(&new_c.id())
+        );
+        let new_b = new_c.parent(0)?;
+        assert_eq!(
+            report
+                .old_to_new_oids
+                .get(label_to_commit_oid.get("B").unwrap()),
+            Some(&new_b.id())
+        );
+        assert_eq!(new_b.parent(0)?.message(), Some("Edited A"));
+
+        // THEN neither the boundary nor the commit past it are rewritten, and master stays put.
+        for label in &["D", "E"] {
+            assert!(
+                !report
+                    .old_to_new_oids
+                    .contains_key(label_to_commit_oid.get(label).unwrap()),
+                "Commit {} should not be rewritten",
                 label
             );
+        }
+        assert_eq!(
+            repo.find_reference("refs/heads/master")?.target(),
+            Some(*label_to_commit_oid.get("E").unwrap())
+        );
+
+        Ok(())
+    }
+
+    /// An edit to make to a randomly generated graph.
+    #[derive(Clone, Debug)]
+    enum GraphEdit {
+        Message,
+        /// Replace the tree with the tree of any commit.
+        Tree(sample::Index),
+        /// Replace the parents with commits created before the edited commit, so that the graph
+        /// stays acyclic.
+        Parents(Vec<sample::Index>),
+    }
+
+    /// The parents of each commit of a graph, as choices among the commits created before it.
+    fn graph_strategy() -> impl Strategy<Value = Vec<Vec<sample::Index>>> {
+        prop::collection::vec(prop::collection::vec(any::<sample::Index>(), 0..=2), 1..8)
+    }
+
+    fn graph_edit_strategy() -> impl Strategy<Value = GraphEdit> {
+        prop_oneof![
+            Just(GraphEdit::Message),
+            any::<sample::Index>().prop_map(GraphEdit::Tree),
+            prop::collection::vec(any::<sample::Index>(), 0..=2).prop_map(GraphEdit::Parents),
+        ]
+    }
+
+    /// Picks distinct labels among the first `count` labels, keeping the order they were chosen.
+    fn choose_labels<'a>(
+        labels: &'a [String],
+        count: usize,
+        choices: &[sample::Index],
+    ) -> Vec<&'a str> {
+        let mut chosen = Vec::new();
+        if count == 0 {
+            return chosen;
+        }
+        for choice in choices {
+            let label = labels[choice.index(count)].as_str();
+            if !chosen.contains(&label) {
+                chosen.push(label);
+            }
+        }
+        chosen
+    }
+
+    fn check_graph_edit_invariants(
+        graph: &[Vec<sample::Index>],
+        edited: sample::Index,
+        graph_edit: &GraphEdit,
+    ) -> Result<()> {
+        let labels: Vec<String> = (0..graph.len()).map(|i| format!("C{}", i)).collect();
+        let parents: Vec<Vec<&str>> = graph
+            .iter()
+            .enumerate()
+            .map(|(i, choices)| choose_labels(&labels, i, choices))
+            .collect();
+        let spec: Vec<(&str, i64, &[&str])> = labels
+            .iter()
+            .zip(&parents)
+            .enumerate()
+            .map(|(i, (label, parents))| (label.as_str(), i as i64, parents.as_slice()))
+            .collect();
+        // With a branch at every commit without children, so that every commit is reachable.
+        let branch_names: Vec<(String, &str)> = labels
+            .iter()
+            .filter(|label| !parents.iter().flatten().any(|parent| *parent == label.as_str()))
+            .map(|label| (format!("branch-{}", label), label.as_str()))
+            .collect();
+        let branches: Vec<(&str, &str)> = branch_names
+            .iter()
+            .map(|(name, label)| (name.as_str(), *label))
+            .collect();
+        let (repo, label_to_commit_oid, _dir) = given_repository(&spec, &branches)?;
+
+        let edited_index = edited.index(labels.len());
+        let commit_to_edit = repo.find_commit(label_to_commit_oid[labels[edited_index].as_str()])?;
+        let tree;
+        let new_parents: Vec<Commit>;
+        let new_parent_refs: Vec<&Commit>;
+        let mut edit = CommitEdit::new();
+        // The tree and parents the edited commit should end up with.
+        let mut edited_tree_id = commit_to_edit.tree_id();
+        let mut edited_parent_ids: Vec<Oid> = commit_to_edit.parent_ids().collect();
+        match graph_edit {
+            GraphEdit::Message => {
+                edit.edit_message("Edited");
+            }
+            GraphEdit::Tree(choice) => {
+                let label = labels[choice.index(labels.len())].as_str();
+                tree = repo.find_commit(label_to_commit_oid[label])?.tree()?;
+                edit.edit_tree(&tree);
+                edited_tree_id = tree.id();
+            }
+            GraphEdit::Parents(choices) => {
+                new_parents = choose_labels(&labels, edited_index, choices)
+                    .into_iter()
+                    .map(|label| repo.find_commit(label_to_commit_oid[label]))
+                    .collect::<Result<_, _>>()?;
+                new_parent_refs = new_parents.iter().collect();
+                edit.edit_parents(&new_parent_refs);
+                edited_parent_ids = new_parents.iter().map(Commit::id).collect();
+            }
+        }
+
+        let report = match Regraph::new(&repo).allow_non_fast_forward(true).run(
+            RefArg::AllLocalRefs,
+            &commit_to_edit,
+            &edit,
+        ) {
+            Err(RegraphError::NoChange) => return Ok(()),
+            result => result?,
+        };
+        let old_to_new_oids = &report.old_to_new_oids;
+
+        // THEN every branch points to the rewritten counterpart of its original commit, if any.
+        for (name, label) in &branches {
+            let old_oid = label_to_commit_oid[label];
             assert_eq!(
-                new_commit.committer().when().sign(),
-                old_commit.committer().when().sign(),
-                "{}'s committer date should be untouched",
+                repo.find_reference(&format!("refs/heads/{}", name))?.target(),
+                Some(*old_to_new_oids.get(&old_oid).unwrap_or(&old_oid)),
+                "Branch {} should point to the rewritten {}",
+                name,
                 label
             );
         }
 
+        // THEN the edited commit has the edited tree and parents.
+        let new_edited_commit = repo.find_commit(old_to_new_oids[&commit_to_edit.id()])?;
+        assert_eq!(new_edited_commit.tree_id(), edited_tree_id);
+        assert_eq!(new_edited_commit.parent_ids().collect::<Vec<_>>(), edited_parent_ids);
+
+        for (old_oid, new_oid) in old_to_new_oids {
+            if *old_oid == commit_to_edit.id() {
+                continue;
+            }
+            let old_commit = repo.find_commit(*old_oid)?;
+            let new_commit = repo.find_commit(*new_oid)?;
+
+            // THEN the trees of commits other than the edited commit are preserved.
+            assert_eq!(new_commit.tree_id(), old_commit.tree_id());
+
+            // THEN their parents are replaced in place by their rewritten counterparts, keeping
+            // the first parent first.
+            let mut expected_parent_ids = Vec::new();
+            for parent_id in old_commit.parent_ids() {
+                let parent_id = *old_to_new_oids.get(&parent_id).unwrap_or(&parent_id);
+                if !expected_parent_ids.contains(&parent_id) {
+                    expected_parent_ids.push(parent_id);
+                }
+            }
+            assert_eq!(new_commit.parent_ids().collect::<Vec<_>>(), expected_parent_ids);
+        }
+
+        // THEN no rewritten commit can still be reached from the updated refs.
+        repo.regraph_verify(&report)?;
+
         Ok(())
     }
 
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Failures shrink towards the smallest graph and edit that still break an invariant.
+        #[test]
+        fn it_keeps_invariants_when_editing_random_graphs(
+            graph in graph_strategy(),
+            edited in any::<sample::Index>(),
+            graph_edit in graph_edit_strategy(),
+        ) {
+            check_graph_edit_invariants(&graph, edited, &graph_edit).unwrap();
+        }
+    }
+
     #[test]
     #[ignore]
     fn it_update_notes() -> Result<()> {
@@ -601,21 +3992,164 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
-    fn it_can_squash_some_commits() {
-        todo!();
+    fn it_can_squash_some_commits() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With commits that will be squashed away.
+                ("B", 1, &["A"]), // ...
+                ("C", 2, &["B"]), // With a commit in the middle of the chain to squash into.
+                ("D", 3, &["C"]), // With descendants to be rewritten.
+                ("E", 4, &["D"]), // ...
+            ],
+            &[("master", "E")],
+        )?;
+
+        // WHEN we squash A-B-C by removing the parents of C.
+        Regraph::new(&repo).allow_non_fast_forward(true).run(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_parents(&[]),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN A and B drop out of the graph.
+        assert!(!commits.contains_key("A"), "Commit 'A' should be dropped");
+        assert!(!commits.contains_key("B"), "Commit 'B' should be dropped");
+
+        // THEN C becomes the root.
+        assert_eq!(
+            commits.get("C").unwrap().parent_count(),
+            0,
+            "Commit 'C' should have no parent."
+        );
+
+        // THEN D and E are rewritten on top of the new C, keeping their trees.
+        for (label, parent_label) in &[("D", "C"), ("E", "D")] {
+            let new_commit = commits.get(*label).unwrap();
+            let old_commit = repo.find_commit(*label_to_commit_oid.get(label).unwrap())?;
+            assert_ne!(new_commit.id(), old_commit.id(), "{} should be rewritten", label);
+            assert_eq!(
+                new_commit.parent_id(0)?,
+                commits.get(*parent_label).unwrap().id(),
+                "{} should be rewritten on top of the new {}",
+                label,
+                parent_label
+            );
+            assert_eq!(
+                new_commit.tree_id(),
+                old_commit.tree_id(),
+                "{}'s tree should be untouched",
+                label
+            );
+        }
+
+        Ok(())
     }
 
     #[test]
-    #[ignore]
-    fn it_can_change_authors() {
-        todo!();
+    fn it_can_change_authors() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With an ancestor that shouldn't be touched.
+                ("B", 1, &["A"]), // With a commit whose author will be renamed.
+                ("C", 2, &["B"]), // With a descendant that keeps its own author.
+            ],
+            &[("master", "C")],
+        )?;
+        let original_b = repo.find_commit(*label_to_commit_oid.get("B").unwrap())?;
+        let original_c = repo.find_commit(*label_to_commit_oid.get("C").unwrap())?;
+
+        // WHEN we change only the name of B's author.
+        repo.regraph(
+            RefArg::AllLocalRefs,
+            &original_b,
+            CommitEdit::new().edit_author_name("New author"),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN A is untouched.
+        assert_eq!(
+            commits.get("A").unwrap().id(),
+            *label_to_commit_oid.get("A").unwrap(),
+            "Commit 'A' should remain unaffected"
+        );
+
+        // THEN B's author name changes, keeping its email and date.
+        let new_b = commits.get("B").unwrap();
+        assert_eq!(new_b.author().name(), Some("New author"));
+        assert_eq!(new_b.author().email(), Some("B-email"));
+        assert_eq!(
+            new_b.author().when().seconds(),
+            original_b.author().when().seconds(),
+            "B's author date should be untouched"
+        );
+
+        // THEN B's committer is untouched.
+        assert_eq!(new_b.committer().name(), Some("B-comitter"));
+
+        // THEN C is rewritten onto the new B, but keeps its own author.
+        let new_c = commits.get("C").unwrap();
+        assert_ne!(new_c.id(), original_c.id(), "Commit 'C' should be rewritten");
+        assert_eq!(new_c.parent_id(0)?, new_b.id());
+        assert_eq!(new_c.author().name(), Some("C-author"));
+        assert_eq!(new_c.author().email(), Some("C-email"));
+
+        Ok(())
     }
 
     #[test]
-    #[ignore]
-    fn it_can_change_committers() {
-        todo!();
+    fn it_can_change_committers() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With an ancestor that shouldn't be touched.
+                ("B", 1, &["A"]), // With a commit whose committer will be replaced.
+                ("C", 2, &["B"]), // With a descendant that keeps its own committer.
+            ],
+            &[("master", "C")],
+        )?;
+        let original_c = repo.find_commit(*label_to_commit_oid.get("C").unwrap())?;
+
+        // WHEN we replace B's committer entirely.
+        let committer = Signature::new("New committer", "new@example.com", &Time::new(100, 60))?;
+        repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_committer(&committer),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN A is untouched.
+        assert_eq!(
+            commits.get("A").unwrap().id(),
+            *label_to_commit_oid.get("A").unwrap(),
+            "Commit 'A' should remain unaffected"
+        );
+
+        // THEN B's committer is replaced, including the date.
+        let new_b = commits.get("B").unwrap();
+        assert_eq!(new_b.committer().name(), Some("New committer"));
+        assert_eq!(new_b.committer().email(), Some("new@example.com"));
+        assert_eq!(new_b.committer().when().seconds(), 100);
+        assert_eq!(new_b.committer().when().offset_minutes(), 60);
+
+        // THEN B's author is untouched.
+        assert_eq!(new_b.author().name(), Some("B-author"));
+
+        // THEN C is rewritten onto the new B, but keeps its own committer.
+        let new_c = commits.get("C").unwrap();
+        assert_ne!(new_c.id(), original_c.id(), "Commit 'C' should be rewritten");
+        assert_eq!(new_c.parent_id(0)?, new_b.id());
+        assert_eq!(new_c.committer().name(), Some("C-comitter"));
+        assert_eq!(
+            new_c.committer().when().seconds(),
+            original_c.committer().when().seconds(),
+            "C's committer date should be untouched"
+        );
+
+        Ok(())
     }
 
     #[test]
@@ -631,15 +4165,150 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
-    fn it_can_append_parents() {
-        todo!();
+    fn it_can_append_parents() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a linear commit that will gain a parent.
+                ("C", 2, &["B"]), // With a descendant.
+                ("X", 3, &[]),    // With an unrelated commit to append as a parent.
+            ],
+            &[("master", "C"), ("side", "X")],
+        )?;
+        let original_b = repo.find_commit(*label_to_commit_oid.get("B").unwrap())?;
+        assert_eq!(original_b.parent_count(), 1);
+
+        // WHEN we append X as a parent of B.
+        let x = repo.find_commit(*label_to_commit_oid.get("X").unwrap())?;
+        repo.regraph(
+            RefArg::AllLocalRefs,
+            &original_b,
+            CommitEdit::new().add_parent(&x),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+
+        // THEN B becomes a merge, keeping A as its first parent.
+        let new_b = commits.get("B").unwrap();
+        assert_eq!(new_b.parent_count(), 2, "Commit 'B' should have 2 parents.");
+        assert_eq!(new_b.parent_id(0)?, *label_to_commit_oid.get("A").unwrap());
+        assert_eq!(new_b.parent_id(1)?, x.id());
+
+        // THEN C is rewritten on top of the new B.
+        assert_eq!(commits.get("C").unwrap().parent_id(0)?, new_b.id());
+
+        // THEN X is untouched.
+        assert_eq!(
+            repo.find_reference("refs/heads/side")?.target(),
+            Some(x.id()),
+            "side should still point to the original 'X'"
+        );
+
+        Ok(())
     }
 
     #[test]
-    #[ignore]
-    fn it_can_swap_trees() {
-        todo!();
+    fn it_ignores_appended_parents_the_commit_already_has() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a linear commit that will gain a parent.
+                ("X", 2, &[]),    // With an unrelated commit to append as a parent.
+            ],
+            &[("master", "B"), ("side", "X")],
+        )?;
+
+        // WHEN we append A, which is already a parent of B, and X twice.
+        let a = repo.find_commit(*label_to_commit_oid.get("A").unwrap())?;
+        let x = repo.find_commit(*label_to_commit_oid.get("X").unwrap())?;
+        repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().add_parent(&a).add_parent(&x).add_parent(&x),
+        )?;
+
+        // THEN each parent is only kept once, in order.
+        let new_b = repo.find_reference("refs/heads/master")?.peel_to_commit()?;
+        assert_eq!(new_b.parent_ids().collect::<Vec<_>>(), vec![a.id(), x.id()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_can_swap_trees() -> Result<()> {
+        fn shared_file_contents(repo: &Repository, commit: &Commit) -> Result<String> {
+            let entry = commit.tree()?.get_name("shared").unwrap().id();
+            Ok(String::from_utf8(repo.find_blob(entry)?.content().to_vec())?)
+        }
+
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a commit whose tree we'll reuse.
+                ("B", 1, &["A"]), // With a commit whose tree will be swapped.
+                ("C", 2, &["B"]), // With a descendant.
+            ],
+            &[("master", "C")],
+        )?;
+        let a_tree_oid = repo
+            .find_commit(*label_to_commit_oid.get("A").unwrap())?
+            .tree_id();
+
+        // WHEN we swap B's tree for A's tree, by its OID.
+        repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_tree_oid(a_tree_oid),
+        )?;
+        let commits = label_to_commit_reachable_from_ref(&repo, "HEAD")?;
+        let new_b = commits.get("B").unwrap();
+
+        // THEN B has A's tree and contents.
+        assert_eq!(new_b.tree_id(), a_tree_oid, "B should have A's tree");
+        assert_eq!(shared_file_contents(&repo, new_b)?, "A\n");
+
+        // THEN B's parents and message are intact.
+        assert_eq!(
+            new_b.parent_ids().collect::<Vec<_>>(),
+            vec![*label_to_commit_oid.get("A").unwrap()]
+        );
+        assert_eq!(new_b.message(), Some("B"));
+
+        // THEN C is rewritten on top of the new B with its own tree.
+        let new_c = commits.get("C").unwrap();
+        assert_eq!(new_c.parent_id(0)?, new_b.id());
+        assert_eq!(shared_file_contents(&repo, new_c)?, "C\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_when_swapping_in_a_non_tree() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit whose tree will be swapped.
+            ],
+            &[("master", "B")],
+        )?;
+
+        // WHEN we swap B's tree for an OID that is a commit rather than a tree.
+        let a = *label_to_commit_oid.get("A").unwrap();
+        let result = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_tree_oid(a),
+        );
+
+        // THEN the regraph fails, naming the offending OID.
+        assert!(
+            matches!(result, Err(RegraphError::NotATree { oid, .. }) if oid == a),
+            "A commit OID should be rejected as a tree"
+        );
+
+        Ok(())
     }
 
     #[test]
@@ -649,8 +4318,74 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
-    fn it_errors_when_edit_does_no_change() {
-        todo!();
+    fn it_errors_when_edit_does_no_change() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit to be edited.
+                ("C", 2, &["B"]), // With a descendant.
+            ],
+            &[("master", "C")],
+        )?;
+
+        // WHEN we "edit" B's message to the message it already has.
+        let result = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("B"),
+        );
+
+        // THEN the regraph reports that nothing changed.
+        assert!(
+            matches!(result, Err(RegraphError::NoChange)),
+            "An edit that does not change the commit should be reported as NoChange"
+        );
+
+        // THEN no refs are moved.
+        assert_eq!(
+            repo.find_reference("refs/heads/master")?.target().unwrap(),
+            *label_to_commit_oid.get("C").unwrap(),
+            "master should still point to the original 'C'"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_whether_descendants_were_rewritten() -> Result<()> {
+        // GIVEN a repo...
+        let (repo, label_to_commit_oid, _dir) = given_repository(
+            &[
+                ("A", 0, &[]),    // With a root commit.
+                ("B", 1, &["A"]), // With a commit in the middle.
+                ("C", 2, &["B"]), // With a tip commit.
+            ],
+            &[("master", "C")],
+        )?;
+
+        // WHEN we edit the tip commit.
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("C").unwrap())?,
+            CommitEdit::new().edit_message("Edited C"),
+        )?;
+
+        // THEN no descendants are reported as rewritten.
+        assert_eq!(report.rewritten_descendants, 0);
+        assert_eq!(report.old_to_new_oids.len(), 1);
+
+        // WHEN we edit the middle commit.
+        let report = repo.regraph(
+            RefArg::AllLocalRefs,
+            &repo.find_commit(*label_to_commit_oid.get("B").unwrap())?,
+            CommitEdit::new().edit_message("Edited B"),
+        )?;
+
+        // THEN the tip is reported as a rewritten descendant.
+        assert_eq!(report.rewritten_descendants, 1);
+        assert_eq!(report.old_to_new_oids.len(), 2);
+
+        Ok(())
     }
 }